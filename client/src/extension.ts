@@ -43,6 +43,7 @@ export async function activate(context: ExtensionContext) {
 			const columnToShowIn = window.activeTextEditor ? window.activeTextEditor
 				.viewColumn : undefined;
 
+			const path = window.activeTextEditor?.document.uri.toString();
 			if (currentPanel) {
 				// If we already have a panel, show it in the target column
 				currentPanel.reveal(columnToShowIn);
@@ -54,9 +55,18 @@ export async function activate(context: ExtensionContext) {
 					ViewColumn.Two, // Editor column to show the new webview panel in.
 					{}, // Webview options. More on these later.
 				);
-				client.sendRequest("tjs-postfix/ast-preview", {
-					path: window.activeTextEditor.document.uri.toString(),
-				});
+			}
+			if (path) {
+				const panel = currentPanel;
+				client
+					.sendRequest<{ path: string; ast: string }>("tjs-postfix/ast-preview", {
+						path,
+					})
+					.then((result) => {
+						panel.webview.html = getWebContent(result.path, escapeHtml(result.ast));
+					}, (e) => {
+						console.warn(e);
+					});
 			}
 
 			currentPanel.onDidDispose(() => {
@@ -124,8 +134,8 @@ console.timeEnd('${result}')`,
 			{ scheme: "file", language: "javascriptreact" },
 		],
 		synchronize: {
-			// Notify the server about file changes to '.clientrc files contained in the workspace
-			fileEvents: workspace.createFileSystemWatcher("**/.clientrc"),
+			// Notify the server about changes of workspace template files and `.editorconfig`
+			fileEvents: workspace.createFileSystemWatcher("**/{.tjs-postfix.json,tjs-postfix.toml,.editorconfig}"),
 		},
 		middleware: {},
 		traceOutputChannel,
@@ -212,3 +222,10 @@ function getWebContent(path: string, astString: string): string {
     <pre>${astString}</pre>
   `;
 }
+
+function escapeHtml(content: string): string {
+	return content
+		.replace(/&/g, "&amp;")
+		.replace(/</g, "&lt;")
+		.replace(/>/g, "&gt;");
+}
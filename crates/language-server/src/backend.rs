@@ -1,231 +1,464 @@
-use lsp_text_document::FullTextDocument;
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use dashmap::DashMap;
+use ropey::Rope;
+use std::collections::HashSet;
 use std::sync::Arc;
 use std::sync::Mutex as StdMutex;
+use std::sync::OnceLock;
+use std::time::Duration;
 use tokio::sync::Mutex;
-use tower_lsp::{lsp_types::*, Client};
-use tree_sitter::{Node, Parser, Tree};
-
-#[derive(Serialize, Deserialize, Debug)]
-#[serde(rename_all = "camelCase")]
-pub struct PostfixTemplate {
-    snippet_key: String,
-    code: String,
-}
+use tower_lsp::{jsonrpc, lsp_types::*, Client};
+use tree_sitter::{Node, Tree};
+
+use crate::completion::{CompletionCapability, PostfixRange};
+use crate::document::{DocumentSnapshot, DocumentState};
+use crate::indent::IndentStyle;
+use crate::language::LanguageKind;
+use crate::notification::{AstNode, AstPreviewRequestParams, AstPreviewResponse};
+use crate::position::PositionEncoding;
+use crate::postfix_context::PostfixContext;
+use crate::query_registry::QueryRegistry;
+use crate::receiver::ReceiverText;
+use crate::template::{
+    builtin_template_list, compile_template_list, read_workspace_template, CompiledTemplate,
+    TemplateSource, WorkspaceTemplate,
+};
+use crate::type_query::{parse_type_query_command, TypeInfo, TypeQueryClient};
 
-pub struct SnippetCompletionItem {
-    label: String,
-    detail: String,
-    replace_string_generator: Box<dyn Fn(String) -> String>,
+/// how long a request waits for the parse of latest version before using an older snapshot
+const SNAPSHOT_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// `workspaceFolders` of `initialize`, or the folder of `rootUri` for clients without
+/// workspace folders support
+pub(crate) fn initial_workspace_folder_list(params: &InitializeParams) -> Vec<WorkspaceFolder> {
+    if let Some(folder_list) = &params.workspace_folders {
+        return folder_list.clone();
+    }
+    #[allow(deprecated)]
+    let root_uri = params.root_uri.clone();
+    root_uri
+        .map(|uri| {
+            let name = uri
+                .path_segments()
+                .and_then(|segment_list| segment_list.rev().find(|segment| !segment.is_empty()))
+                .unwrap_or_default()
+                .to_string();
+            vec![WorkspaceFolder { uri, name }]
+        })
+        .unwrap_or_default()
 }
 
 pub struct Backend {
     pub(crate) client: Client,
-    pub(crate) document_map: Mutex<HashMap<String, FullTextDocument>>,
-    pub(crate) parser: Mutex<Parser>,
-    pub(crate) parse_tree_map: Mutex<HashMap<String, Tree>>,
-    postfix_template_list: Arc<StdMutex<Vec<PostfixTemplate>>>,
+    /// never hold an entry of it across an `.await`
+    pub(crate) document_map: Arc<DashMap<Url, DocumentState>>,
+    pub(crate) query_registry: QueryRegistry,
+    /// negotiated in `initialize`
+    pub(crate) position_encoding: OnceLock<PositionEncoding>,
+    /// negotiated in `initialize`
+    pub(crate) completion_capability: OnceLock<CompletionCapability>,
+    /// user templates, which override built-in ones of the same key
+    postfix_template_list: Arc<StdMutex<Vec<CompiledTemplate>>>,
+    /// keys of `tjs-postfix.disabledBuiltinTemplates`
+    disabled_builtin_template_set: StdMutex<HashSet<String>>,
+    /// templates of `.tjs-postfix.json` or `tjs-postfix.toml` in workspace folders, which
+    /// override user ones
+    workspace_template_list: StdMutex<Vec<WorkspaceTemplate>>,
+    /// spawned in `initialized` if `tjs-postfix.typeQueryCommand` is configured
+    type_query: OnceLock<TypeQueryClient>,
+    /// indent style of opened documents, cleared once the configuration or any
+    /// `.editorconfig` changes
+    indent_style_map: DashMap<Url, IndentStyle>,
     pub workspace_folder: Mutex<Vec<WorkspaceFolder>>,
 }
 impl Backend {
     pub fn new(
         client: Client,
-        document_map: Mutex<HashMap<String, FullTextDocument>>,
-        parser: Mutex<Parser>,
-        postfix_template_list: Arc<StdMutex<Vec<PostfixTemplate>>>,
-        parse_tree_map: Mutex<HashMap<String, Tree>>,
+        document_map: Arc<DashMap<Url, DocumentState>>,
+        postfix_template_list: Arc<StdMutex<Vec<CompiledTemplate>>>,
+        query_registry: QueryRegistry,
     ) -> Self {
         Self {
             client,
             document_map,
-            parser,
             postfix_template_list,
-            parse_tree_map,
+            disabled_builtin_template_set: StdMutex::new(HashSet::new()),
+            workspace_template_list: StdMutex::new(vec![]),
+            query_registry,
+            position_encoding: OnceLock::new(),
+            completion_capability: OnceLock::new(),
+            type_query: OnceLock::new(),
+            indent_style_map: DashMap::new(),
             workspace_folder: Mutex::new(vec![]),
         }
     }
 
-    pub(crate) async fn reset_templates(&self) {
+    /// handler of the `tjs-postfix/ast-preview` custom request
+    pub async fn ast_preview(
+        &self,
+        params: AstPreviewRequestParams,
+    ) -> jsonrpc::Result<AstPreviewResponse> {
+        let uri = Url::parse(&params.path).map_err(|err| {
+            jsonrpc::Error::invalid_params(format!("{} is not a uri: {}", params.path, err))
+        })?;
+        let snapshot = self.get_snapshot(&uri).await.ok_or_else(|| {
+            jsonrpc::Error::invalid_params(format!("{} is not opened", params.path))
+        })?;
+        let tree = &snapshot.tree;
+        let tree_json = if params.include_tree {
+            Some(AstNode::from_cursor(&mut tree.walk()))
+        } else {
+            None
+        };
+        Ok(AstPreviewResponse {
+            ast: format!("{}", TreeWrapper(tree.clone())),
+            tree: tree_json,
+            path: params.path,
+        })
+    }
+
+    pub(crate) fn position_encoding(&self) -> PositionEncoding {
+        self.position_encoding.get().copied().unwrap_or_default()
+    }
+
+    /// the snapshot of current version of the document, or the latest one if the parse
+    /// doesn't finish in time
+    pub(crate) async fn get_snapshot(&self, uri: &Url) -> Option<Arc<DocumentSnapshot>> {
+        let (revision, mut receiver) = {
+            let state = self.document_map.get(uri)?;
+            (state.revision, state.snapshot_sender.subscribe())
+        };
+        let snapshot = tokio::time::timeout(
+            SNAPSHOT_TIMEOUT,
+            receiver.wait_for(|snapshot| {
+                snapshot
+                    .as_ref()
+                    .is_some_and(|snapshot| snapshot.revision >= revision)
+            }),
+        )
+        .await
+        .ok()
+        .and_then(|snapshot| snapshot.ok().and_then(|snapshot| snapshot.clone()));
+        // `None` until the first parse finishes
+        snapshot.or_else(|| receiver.borrow().clone())
+    }
+
+    /// queries are compiled before the client is connected, report the failures once it is
+    pub(crate) async fn report_query_error(&self) {
+        for error in self.query_registry.error_list.iter() {
+            self.client.log_message(MessageType::ERROR, error).await;
+        }
+        if !self.query_registry.error_list.is_empty() {
+            self.client
+                .show_message(
+                    MessageType::ERROR,
+                    format!(
+                        "tjs-postfix: {} tree-sitter queries failed to compile, see the output for detail",
+                        self.query_registry.error_list.len()
+                    ),
+                )
+                .await;
+        }
+    }
+
+    /// spawn the type query process, type aware templates are offered regardless of the
+    /// type of receiver without it
+    pub(crate) async fn start_type_query(&self) {
         let configuration = self
             .client
             .configuration(vec![ConfigurationItem {
                 scope_uri: None,
-                section: Some("tjs-postfix.templateMapList".into()),
+                section: Some("tjs-postfix.typeQueryCommand".into()),
             }])
             .await;
-        if let Ok(mut configuration) = configuration {
-            if let Ok(configuration) = serde_json::from_value::<Vec<PostfixTemplate>>(
-                configuration.first_mut().unwrap().take(),
-            ) {
-                match self.postfix_template_list.lock() {
-                    Ok(mut list) => {
-                        list.clear();
-                        list.extend(configuration);
-                    }
-                    Err(_) => {}
-                }
+        let (command, args) = match configuration
+            .ok()
+            .and_then(|mut configuration| configuration.pop())
+            .and_then(parse_type_query_command)
+        {
+            Some(command) => command,
+            None => return,
+        };
+        match TypeQueryClient::spawn(&command, &args) {
+            Ok(type_query) => {
+                let _ = self.type_query.set(type_query);
+            }
+            Err(err) => {
+                self.client
+                    .show_message(
+                        MessageType::WARNING,
+                        format!(
+                            "tjs-postfix: failed to spawn type query process `{}`: {}",
+                            command, err
+                        ),
+                    )
+                    .await;
             }
         }
     }
 
-    pub(crate) fn get_template_completion_item_list(
+    /// the type of the expression at each range of `byte_range_list` of the document, all
+    /// `None` if no type query process is running
+    pub(crate) async fn query_type_list(
         &self,
-        source_code: &str,
-        replace_range: &Range,
-    ) -> Vec<CompletionItem> {
-        if let Ok(template_list) = self.postfix_template_list.lock() {
-            template_list
-                .iter()
-                .map(|template_item| {
-                    let mut item = CompletionItem::new_simple(
-                        template_item.snippet_key.clone(),
-                        template_item.code.clone(),
-                    );
-                    item.kind = Some(CompletionItemKind::SNIPPET);
-                    item.insert_text_format = Some(InsertTextFormat::SNIPPET);
-                    let replace_string = template_item.code.replace("$$", source_code);
-                    item.documentation = Some(Documentation::String(replace_string.clone()));
-                    item.text_edit = Some(CompletionTextEdit::Edit(TextEdit::new(
-                        replace_range.clone(),
-                        replace_string.clone(),
-                    )));
-                    item.insert_text = Some(replace_string);
-                    item.additional_text_edits =
-                        Some(vec![TextEdit::new(replace_range.clone(), "".into())]);
-                    item
-                })
-                .collect()
-        } else {
-            vec![]
+        uri: &Url,
+        rope: &Rope,
+        source: &str,
+        byte_range_list: &[std::ops::Range<usize>],
+    ) -> Vec<Option<TypeInfo>> {
+        let (type_query, file_name) = match (self.type_query.get(), uri.to_file_path()) {
+            (Some(type_query), Ok(file_name)) => (type_query, file_name),
+            _ => return vec![None; byte_range_list.len()],
+        };
+        let to_utf16_offset = |byte_index| rope.char_to_utf16_cu(rope.byte_to_char(byte_index));
+        let offset_range_list = byte_range_list
+            .iter()
+            .map(|range| to_utf16_offset(range.start)..to_utf16_offset(range.end))
+            .collect::<Vec<_>>();
+        type_query
+            .query_list(
+                file_name.to_string_lossy().to_string(),
+                source.to_string(),
+                &offset_range_list,
+            )
+            .await
+    }
+
+    pub(crate) async fn reset_templates(&self) {
+        let configuration = self
+            .client
+            .configuration(vec![
+                ConfigurationItem {
+                    scope_uri: None,
+                    section: Some("tjs-postfix.templateMapList".into()),
+                },
+                ConfigurationItem {
+                    scope_uri: None,
+                    section: Some("tjs-postfix.disabledBuiltinTemplates".into()),
+                },
+            ])
+            .await;
+        let (template_value, disabled_value) = match configuration {
+            Ok(configuration) if configuration.len() == 2 => {
+                let mut configuration = configuration.into_iter();
+                (configuration.next().unwrap(), configuration.next().unwrap())
+            }
+            _ => return,
+        };
+        if let Ok(disabled_builtin_template_set) =
+            serde_json::from_value::<HashSet<String>>(disabled_value)
+        {
+            if let Ok(mut set) = self.disabled_builtin_template_set.lock() {
+                *set = disabled_builtin_template_set;
+            }
+        }
+        if template_value.is_null() {
+            return;
+        }
+        let (template_list, error_list) = compile_template_list(template_value, "templateMapList");
+        self.report_template_error(&error_list).await;
+        if let Ok(mut list) = self.postfix_template_list.lock() {
+            list.clear();
+            list.extend(template_list);
+        }
+    }
+
+    /// `editor.insertSpaces` and `editor.tabSize` of the document, overridden by
+    /// `.editorconfig`, asked once until `clear_indent_style` is called
+    pub(crate) async fn indent_style(&self, uri: &Url) -> IndentStyle {
+        if let Some(indent_style) = self.indent_style_map.get(uri) {
+            return *indent_style;
+        }
+        let indent_style = self.read_indent_style(uri).await;
+        self.indent_style_map.insert(uri.clone(), indent_style);
+        indent_style
+    }
+
+    /// forget the indent style of `uri`, or of every document if it is `None`
+    pub(crate) fn clear_indent_style(&self, uri: Option<&Url>) {
+        match uri {
+            Some(uri) => {
+                self.indent_style_map.remove(uri);
+            }
+            None => self.indent_style_map.clear(),
+        }
+    }
+
+    async fn read_indent_style(&self, uri: &Url) -> IndentStyle {
+        let configuration = self
+            .client
+            .configuration(vec![
+                ConfigurationItem {
+                    scope_uri: Some(uri.clone()),
+                    section: Some("editor.insertSpaces".into()),
+                },
+                ConfigurationItem {
+                    scope_uri: Some(uri.clone()),
+                    section: Some("editor.tabSize".into()),
+                },
+            ])
+            .await
+            .unwrap_or_default();
+        let mut indent_style = IndentStyle::default();
+        if let Some(insert_spaces) = configuration.first().and_then(|value| value.as_bool()) {
+            indent_style.insert_spaces = insert_spaces;
+        }
+        if let Some(tab_size) = configuration.get(1).and_then(|value| value.as_u64()) {
+            indent_style.tab_size = tab_size as usize;
+        }
+        match uri.to_file_path() {
+            Ok(file_path) => {
+                tokio::task::spawn_blocking(move || indent_style.with_editorconfig(&file_path))
+                    .await
+                    .unwrap_or(indent_style)
+            }
+            Err(_) => indent_style,
+        }
+    }
+
+    /// read template files of every workspace folder again
+    pub(crate) async fn reload_workspace_templates(&self) {
+        let folder_path_list = self
+            .workspace_folder
+            .lock()
+            .await
+            .iter()
+            .filter_map(|folder| folder.uri.to_file_path().ok())
+            .collect::<Vec<_>>();
+        let read = tokio::task::spawn_blocking(move || {
+            let mut workspace_template_list = vec![];
+            let mut error_list = vec![];
+            for folder_path in folder_path_list {
+                if let Some((workspace_template, folder_error_list)) =
+                    read_workspace_template(&folder_path)
+                {
+                    workspace_template_list.push(workspace_template);
+                    error_list.extend(folder_error_list);
+                }
+            }
+            (workspace_template_list, error_list)
+        })
+        .await;
+        let (workspace_template_list, error_list) = match read {
+            Ok(read) => read,
+            Err(_) => return,
+        };
+        self.report_template_error(&error_list).await;
+        if let Ok(mut list) = self.workspace_template_list.lock() {
+            *list = workspace_template_list;
+        }
+    }
+
+    async fn report_template_error(&self, error_list: &[String]) {
+        for error in error_list.iter() {
+            self.client.log_message(MessageType::ERROR, error).await;
+        }
+        if !error_list.is_empty() {
+            self.client
+                .show_message(
+                    MessageType::ERROR,
+                    format!(
+                        "tjs-postfix: {} invalid templates, {}",
+                        error_list.len(),
+                        error_list[0]
+                    ),
+                )
+                .await;
         }
     }
 
-    pub(crate) fn get_snippet_completion_item_list(
+    /// templates of the innermost workspace folder containing the document come first, then
+    /// user ones and built-in ones, the first template of a key wins
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn get_template_completion_item_list(
         &self,
-        source_code: &str,
-        replace_range: &Range,
+        uri: &Url,
+        receiver: &ReceiverText,
+        receiver_kind: &str,
+        language: LanguageKind,
+        postfix_range: &PostfixRange,
+        context: PostfixContext,
+        type_info: Option<&TypeInfo>,
     ) -> Vec<CompletionItem> {
-        let snippet_list = vec![
-            SnippetCompletionItem {
-                label: String::from("not"),
-                detail: String::from("revert a variable or expression"),
-                replace_string_generator: Box::new(|name| format!("!{}", name)),
-            },
-            SnippetCompletionItem {
-                label: String::from("if"),
-                detail: String::from("if (expr)"),
-                replace_string_generator: Box::new(|name| {
-                    format!(
-                        r#"if ({}) {{
-    ${{0}}
-}}"#,
-                        name
-                    )
-                }),
-            },
-            SnippetCompletionItem {
-                label: String::from("ifn"),
-                detail: String::from("if (!expr)"),
-                replace_string_generator: Box::new(|name| {
-                    format!(
-                        r#"if (!{}) {{
-    ${{0}}
-}}"#,
-                        name
-                    )
-                }),
-            },
-            SnippetCompletionItem {
-                label: String::from("var"),
-                detail: String::from("var name = expr"),
-                replace_string_generator: Box::new(|name| format!("var ${{0}} = {}", name)),
-            },
-            SnippetCompletionItem {
-                label: String::from("call"),
-                detail: String::from("call(expr)"),
-                replace_string_generator: Box::new(|name| format!("${{0}}({})", name)),
-            },
-            SnippetCompletionItem {
-                label: String::from("let"),
-                detail: String::from("let name = expr"),
-                replace_string_generator: Box::new(|name| format!("let ${{0}} = {}", name)),
-            },
-            SnippetCompletionItem {
-                label: String::from("const"),
-                detail: String::from("const name = expr"),
-                replace_string_generator: Box::new(|name| format!("const ${{0}} = {}", name)),
-            },
-            SnippetCompletionItem {
-                label: String::from("cast"),
-                detail: String::from("(<name>expr)"),
-                replace_string_generator: Box::new(|name| format!("(<${{0}}>{})", name)),
-            },
-            SnippetCompletionItem {
-                label: String::from("as"),
-                detail: String::from("(expr as name)"),
-                replace_string_generator: Box::new(|name| format!("({} as ${{0}})", name)),
-            },
-            SnippetCompletionItem {
-                label: String::from("new"),
-                detail: String::from("new expr()"),
-                replace_string_generator: Box::new(|name| format!("new {}()", name)),
-            },
-            SnippetCompletionItem {
-                label: String::from("return"),
-                detail: String::from("return expr"),
-                replace_string_generator: Box::new(|name| format!("return {}", name)),
-            },
-            SnippetCompletionItem {
-                label: String::from("for"),
-                detail: String::from("forloop"),
-                replace_string_generator: Box::new(|name| {
-                    format!(
-                        r#"for (let ${{1:i}} = 0, len = {}.length; ${{1:i}} < len; ${{1:i}}++) {{
-  ${{0}}
-}}"#,
-                        name
-                    )
-                }),
-            },
-            SnippetCompletionItem {
-                label: String::from("forof"),
-                detail: String::from("forof"),
-                replace_string_generator: Box::new(|name| {
-                    format!(
-                        r#"for (let ${{1:item}} of {}) {{
-  ${{0}}
-}}"#,
-                        name
-                    )
-                }),
-            },
-            SnippetCompletionItem {
-                label: String::from("foreach"),
-                detail: String::from("expr.forEach(item => )"),
-                replace_string_generator: Box::new(|name| {
-                    format!(
-                        r#"{}.forEach(${{1:item}} => {{
-    ${{0}}
-}})"#,
-                        name
-                    )
-                }),
-            },
-        ];
-        snippet_list
+        let (workspace_template_list, template_list, disabled_builtin_template_set) = match (
+            self.workspace_template_list.lock(),
+            self.postfix_template_list.lock(),
+            self.disabled_builtin_template_set.lock(),
+        ) {
+            (Ok(workspace_template_list), Ok(template_list), Ok(set)) => {
+                (workspace_template_list, template_list, set)
+            }
+            _ => return vec![],
+        };
+        let document_path = uri.to_file_path().ok();
+        let workspace_template = document_path.and_then(|document_path| {
+            workspace_template_list
+                .iter()
+                .filter(|workspace_template| {
+                    document_path.starts_with(&workspace_template.folder_path)
+                })
+                .max_by_key(|workspace_template| {
+                    workspace_template.folder_path.components().count()
+                })
+        });
+        let folder_template_list = workspace_template
             .into_iter()
-            .map(|snippet| {
-                let mut item = CompletionItem::new_simple(snippet.label, snippet.detail);
-                item.insert_text_format = Some(InsertTextFormat::SNIPPET);
+            .flat_map(|workspace_template| {
+                workspace_template.template_list.iter().map(|template| {
+                    (
+                        template,
+                        TemplateSource::WorkspaceFile(&workspace_template.file_path),
+                    )
+                })
+            });
+        let builtin_template_list = builtin_template_list()
+            .iter()
+            .filter(|builtin| !disabled_builtin_template_set.contains(&builtin.snippet_key))
+            .map(|template| (template, TemplateSource::Builtin));
+        let mut snippet_key_set = HashSet::new();
+        folder_template_list
+            .chain(
+                template_list
+                    .iter()
+                    .map(|template| (template, TemplateSource::UserSetting)),
+            )
+            .chain(builtin_template_list)
+            .filter(|(template, _)| snippet_key_set.insert(template.snippet_key.as_str()))
+            .filter(|(template, _)| template.is_applicable(language, context, receiver_kind))
+            .filter_map(|(template, template_source)| {
+                let satisfied =
+                    template
+                        .type_requirement
+                        .zip(type_info)
+                        .map(|(requirement, type_info)| {
+                            (
+                                requirement.is_strict(),
+                                requirement.is_satisfied_by(type_info),
+                            )
+                        });
+                if let Some((true, false)) = satisfied {
+                    return None;
+                }
+                let mut item = CompletionItem::new_simple(
+                    template.snippet_key.clone(),
+                    template
+                        .description
+                        .clone()
+                        .unwrap_or_else(|| template.code.clone()),
+                );
+                if let Some((_, true)) = satisfied {
+                    item.preselect = Some(true);
+                }
                 item.kind = Some(CompletionItemKind::SNIPPET);
-                let replace_string = (snippet.replace_string_generator)(source_code.to_string());
-                item.documentation = Some(Documentation::String(replace_string.clone()));
-
-                item.insert_text = Some(replace_string);
-                item.additional_text_edits =
-                    Some(vec![TextEdit::new(replace_range.clone(), "".into())]);
-                item
+                item.insert_text_format = Some(InsertTextFormat::SNIPPET);
+                postfix_range
+                    .apply(
+                        &mut item,
+                        template,
+                        receiver.for_template(template),
+                        language,
+                        template_source,
+                    )
+                    .then_some(item)
             })
             .collect()
     }
@@ -266,3 +499,350 @@ pub fn pretty_display(f: &mut std::fmt::Formatter<'_>, root: Node) -> std::fmt::
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::completion::{resolve_postfix_item, PostfixEnvironment, ReactImport};
+    use crate::import::DocumentImport;
+    use crate::query_registry::QueryName;
+    use crate::receiver::postfix_candidate_list;
+    use crate::template::PostfixTemplate;
+    use tower_lsp::{LanguageServer, LspService};
+
+    /// the template items of the postfix at `‸` or ending `source`, when the type query process
+    /// answers `type_info`
+    async fn completion_item_list(
+        source: &str,
+        type_info: Option<TypeInfo>,
+        user_template_list: Vec<CompiledTemplate>,
+        disabled_list: &[&str],
+    ) -> Vec<CompletionItem> {
+        let (service, _socket) = LspService::new(|client| {
+            Backend::new(
+                client,
+                Arc::new(DashMap::new()),
+                Arc::new(StdMutex::new(user_template_list)),
+                QueryRegistry::new(),
+            )
+        });
+        let backend = service.inner();
+        let _ = backend
+            .type_query
+            .set(TypeQueryClient::stub(move |_, _| type_info.clone()));
+        backend
+            .disabled_builtin_template_set
+            .lock()
+            .unwrap()
+            .extend(disabled_list.iter().map(|key| key.to_string()));
+        let (source, cursor) = match source.find('‸') {
+            Some(cursor) => (source.replacen('‸', "", 1), cursor),
+            None => (source.to_string(), source.len()),
+        };
+        let source = source.as_str();
+        let language = LanguageKind::TypeScriptReact;
+        let tree = language
+            .parse_with(&mut |byte_index, _| &source.as_bytes()[byte_index..], None)
+            .unwrap();
+        let root = tree.root_node();
+        let dot_byte = source[..cursor].rfind('.').unwrap();
+        let candidate_list = postfix_candidate_list(language, root, source, dot_byte, cursor);
+        let candidate = &candidate_list[0];
+        let uri = Url::parse("file:///workspace/a.tsx").unwrap();
+        let rope = Rope::from_str(source);
+        let type_info_list = backend
+            .query_type_list(
+                &uri,
+                &rope,
+                source,
+                std::slice::from_ref(&candidate.byte_range),
+            )
+            .await;
+        let query_registry = &backend.query_registry;
+        let environment = PostfixEnvironment {
+            document_import: DocumentImport::new(
+                query_registry.get(language, QueryName::ImportStatement),
+                &rope,
+                PositionEncoding::default(),
+                source,
+                root,
+            ),
+            react_import: ReactImport::new(
+                query_registry.get(language, QueryName::ReactImport),
+                source,
+                root,
+            ),
+            ..PostfixEnvironment::default()
+        };
+        let postfix_range = PostfixRange::new(
+            &rope,
+            PositionEncoding::default(),
+            candidate.byte_range.start,
+            dot_byte,
+            cursor,
+            &candidate.scope,
+            &environment,
+        );
+        backend.get_template_completion_item_list(
+            &uri,
+            &ReceiverText::new(source, candidate.kind, candidate.byte_range.clone()),
+            candidate.kind,
+            language,
+            &postfix_range,
+            candidate.context,
+            type_info_list[0].as_ref(),
+        )
+    }
+
+    /// `(label, preselect)` of the template items of `list.` when the type query process
+    /// answers `type_info`
+    async fn template_item_list(type_info: Option<TypeInfo>) -> Vec<(String, bool)> {
+        completion_item_list("list.", type_info, vec![], &[])
+            .await
+            .into_iter()
+            .map(|item| (item.label, item.preselect == Some(true)))
+            .collect()
+    }
+
+    /// `(new text, additional edits)` of the item of `label`
+    fn item_edit(item_list: &[CompletionItem], label: &str) -> Option<(String, Vec<String>)> {
+        let item = item_list.iter().find(|item| item.label == label)?;
+        let new_text = match item.text_edit.as_ref()? {
+            CompletionTextEdit::Edit(edit) => edit.new_text.clone(),
+            CompletionTextEdit::InsertAndReplace(edit) => edit.new_text.clone(),
+        };
+        let additional_list = item
+            .additional_text_edits
+            .iter()
+            .flatten()
+            .map(|edit| edit.new_text.clone())
+            .collect();
+        Some((new_text, additional_list))
+    }
+
+    fn find<'a>(item_list: &'a [(String, bool)], label: &str) -> Option<&'a (String, bool)> {
+        item_list.iter().find(|(item_label, _)| item_label == label)
+    }
+
+    #[tokio::test]
+    async fn unknown_type_shows_every_template() {
+        let item_list = template_item_list(None).await;
+        for label in ["forof", "await", "not", "if"] {
+            assert_eq!(find(&item_list, label), Some(&(label.into(), false)));
+        }
+    }
+
+    #[tokio::test]
+    async fn strict_requirement_hides_template() {
+        let item_list = template_item_list(Some(TypeInfo {
+            display: "number".into(),
+            ..TypeInfo::default()
+        }))
+        .await;
+        assert_eq!(find(&item_list, "forof"), None);
+        assert_eq!(find(&item_list, "await"), None);
+        // `!value` works for any value
+        assert_eq!(find(&item_list, "not"), Some(&("not".into(), false)));
+        assert_eq!(find(&item_list, "if"), Some(&("if".into(), false)));
+    }
+
+    #[tokio::test]
+    async fn satisfied_requirement_preselects_template() {
+        let item_list = template_item_list(Some(TypeInfo {
+            display: "boolean[]".into(),
+            iterable: true,
+            ..TypeInfo::default()
+        }))
+        .await;
+        assert_eq!(find(&item_list, "forof"), Some(&("forof".into(), true)));
+        assert_eq!(find(&item_list, "await"), None);
+        assert_eq!(find(&item_list, "not"), Some(&("not".into(), false)));
+    }
+
+    #[tokio::test]
+    async fn map_only_for_array() {
+        let label_list = |item_list: Vec<CompletionItem>| -> Vec<String> {
+            item_list.into_iter().map(|item| item.label).collect()
+        };
+        // a string is iterable but has no `.map`
+        let item_list = completion_item_list(
+            "<ul>{list.map‸}</ul>",
+            Some(TypeInfo {
+                display: "string".into(),
+                iterable: true,
+                ..TypeInfo::default()
+            }),
+            vec![],
+            &[],
+        )
+        .await;
+        assert!(!label_list(item_list).contains(&"map".into()));
+        let item_list = completion_item_list(
+            "<ul>{list.map‸}</ul>",
+            Some(TypeInfo {
+                display: "string[]".into(),
+                iterable: true,
+                array: true,
+                ..TypeInfo::default()
+            }),
+            vec![],
+            &[],
+        )
+        .await;
+        assert!(label_list(item_list).contains(&"map".into()));
+    }
+
+    #[tokio::test]
+    async fn hook_imported_if_missing() {
+        let item_list = completion_item_list("count.state", None, vec![], &[]).await;
+        assert_eq!(
+            item_edit(&item_list, "state"),
+            Some((
+                "const [count, setCount] = useState(${0})".into(),
+                vec!["import { useState } from 'react'\n".into()]
+            ))
+        );
+        // from the react module already imported
+        let item_list = completion_item_list(
+            "import { h } from 'preact/hooks'\nfoo.ref",
+            None,
+            vec![],
+            &[],
+        )
+        .await;
+        assert_eq!(
+            item_edit(&item_list, "ref"),
+            Some((
+                "const ${1:ref} = useRef(foo)".into(),
+                vec![", useRef".into()]
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn hook_called_through_import() {
+        let item_list =
+            completion_item_list("import React from 'react'\nfoo.ref", None, vec![], &[]).await;
+        assert_eq!(
+            item_edit(&item_list, "ref"),
+            Some(("const ${1:ref} = React.useRef(foo)".into(), vec![]))
+        );
+        let item = item_list.iter().find(|item| item.label == "ref").unwrap();
+        assert_eq!(
+            item.detail.as_deref(),
+            Some("const ref = React.useRef(expr)")
+        );
+        let item_list = completion_item_list(
+            "import { useRef as useR } from 'react'\nfoo.ref",
+            None,
+            vec![],
+            &[],
+        )
+        .await;
+        assert_eq!(
+            item_edit(&item_list, "ref"),
+            Some(("const ${1:ref} = useR(foo)".into(), vec![]))
+        );
+    }
+
+    #[tokio::test]
+    async fn hook_overridden_or_disabled() {
+        let user_template = CompiledTemplate::compile(&PostfixTemplate {
+            snippet_key: "state".into(),
+            code: "let ${expr} = signal()".into(),
+            description: None,
+            languages: None,
+            when: None,
+            context_list: None,
+            type_requirement: None,
+            requires_import: None,
+            target: None,
+            mark_async: None,
+        })
+        .unwrap();
+        let item_list = completion_item_list("count.state", None, vec![user_template], &[]).await;
+        let state_list = item_list
+            .iter()
+            .filter(|item| item.label == "state")
+            .collect::<Vec<_>>();
+        assert_eq!(state_list.len(), 1);
+        assert_eq!(
+            item_edit(&item_list, "state"),
+            Some(("let count = signal()".into(), vec![]))
+        );
+        let item_list = completion_item_list("count.state", None, vec![], &["state"]).await;
+        assert_eq!(item_edit(&item_list, "state"), None);
+    }
+
+    #[tokio::test]
+    async fn wrapping_template_preview() {
+        let item_list =
+            completion_item_list("if (a) {\n  foo(1,\n    2).try", None, vec![], &[]).await;
+        let item = item_list
+            .into_iter()
+            .find(|item| item.label == "try")
+            .unwrap();
+        let item = resolve_postfix_item(item);
+        let documentation = match item.documentation {
+            Some(Documentation::MarkupContent(content)) => content.value,
+            _ => panic!("no preview"),
+        };
+        assert!(
+            documentation.starts_with(
+                "```tsx\n  try {\n      foo(1,\n        2)\n  } catch (e) {\n      \n  }\n```"
+            ),
+            "{}",
+            documentation
+        );
+    }
+
+    #[test]
+    fn workspace_folder_from_root_uri() {
+        let folder = |uri: &str, name: &str| WorkspaceFolder {
+            uri: Url::parse(uri).unwrap(),
+            name: name.into(),
+        };
+        #[allow(deprecated)]
+        let params = InitializeParams {
+            root_uri: Some(Url::parse("file:///home/project/").unwrap()),
+            ..InitializeParams::default()
+        };
+        assert_eq!(
+            initial_workspace_folder_list(&params),
+            [folder("file:///home/project/", "project")]
+        );
+        let params = InitializeParams {
+            workspace_folders: Some(vec![folder("file:///a", "a"), folder("file:///b", "b")]),
+            ..params
+        };
+        assert_eq!(initial_workspace_folder_list(&params).len(), 2);
+        assert!(initial_workspace_folder_list(&InitializeParams::default()).is_empty());
+    }
+
+    #[tokio::test]
+    async fn first_snapshot_parsed_by_worker() {
+        let (service, _socket) = LspService::new(|client| {
+            Backend::new(
+                client,
+                Arc::new(DashMap::new()),
+                Arc::new(StdMutex::new(vec![])),
+                QueryRegistry::new(),
+            )
+        });
+        let backend = service.inner();
+        let uri = Url::parse("file:///workspace/a.ts").unwrap();
+        backend
+            .did_open(DidOpenTextDocumentParams {
+                text_document: TextDocumentItem::new(
+                    uri.clone(),
+                    "typescript".into(),
+                    1,
+                    "let a = 1;".into(),
+                ),
+            })
+            .await;
+        let snapshot = backend.get_snapshot(&uri).await.unwrap();
+        assert_eq!(snapshot.source, "let a = 1;");
+        assert_eq!(snapshot.tree.root_node().kind(), "program");
+    }
+}
@@ -1,19 +1,25 @@
 use std::{
     collections::{HashMap, HashSet},
+    fmt::Write,
     time::Instant,
 };
 
+use inflector::Inflector;
 use log::debug;
 use lsp_text_document::lsp_types::{
     CodeAction, CodeActionKind, CodeActionOrCommand, CodeActionParams, CodeActionResponse, Range,
-    TextEdit, WorkspaceEdit,
+    TextEdit, Url, WorkspaceEdit,
 };
 use serde::{Deserialize, Serialize};
 use streaming_iterator::StreamingIterator;
-use tower_lsp::jsonrpc;
-use tree_sitter::{Language, Node, Point, Query, QueryCursor, TextProvider};
+use tree_sitter::{Node, Query, QueryCursor};
 
-use crate::{helper::generate_lsp_range, query_pattern::FUNCTION_LIKE_DECLARATION, Backend};
+use crate::{
+    document::DocumentSnapshot,
+    indent::{reindent, space_unit, IndentStyle},
+    query_registry::QueryName,
+    Backend,
+};
 #[derive(Serialize, Deserialize)]
 pub struct IdentifierNode {
     start: usize,
@@ -28,22 +34,24 @@ pub struct ExtractComponentData {
     jsx_element_range: Range,
     identifier_node_list: Vec<IdentifierNode>,
     function_name: String,
+    /// e.g. `key={item.id}`, which stays on the call site since react reads it there
+    key: Option<String>,
 }
 pub async fn get_function_call_action(
     back_end: &Backend,
     params: CodeActionParams,
 ) -> Option<CodeActionResponse> {
     let mut ret = CodeActionResponse::new();
-    let document_map = back_end.document_map.lock().await;
-    let document = document_map.get(&params.text_document.uri.to_string())?;
-
-    let map = back_end.parse_tree_map.lock().await;
-    let tree = map.get(&params.text_document.uri.to_string())?;
+    let snapshot = back_end.get_snapshot(&params.text_document.uri).await?;
+    let DocumentSnapshot {
+        rope, source, tree, ..
+    } = &*snapshot;
+    let encoding = back_end.position_encoding();
     let duration = Instant::now();
     let root = tree.root_node();
     let range = params.range;
-    let start = range.start;
-    let end = range.end;
+    let start_byte = encoding.position_to_byte(rope, range.start)?;
+    let end_byte = encoding.position_to_byte(rope, range.end)?;
 
     // debug!("range_string, {:?}", document.rope.get_slice(star..end_char));
     // let start_char =
@@ -52,14 +60,8 @@ pub async fn get_function_call_action(
     // let start_byte = document.rope.try_char_to_byte(start_char).ok()?;
     // let end_byte = document.rope.try_char_to_byte(end_char).ok()?;
     // debug!("text: {}", &document.get_text()[start_byte..end_byte]);
-    let start_node = root.named_descendant_for_point_range(
-        Point::new(start.line as usize, start.character as usize),
-        Point::new(start.line as usize, start.character as usize + 1),
-    )?;
-    let end_node = root.named_descendant_for_point_range(
-        Point::new(end.line as usize, end.character as usize),
-        Point::new(end.line as usize, end.character as usize + 1),
-    )?;
+    let start_node = root.named_descendant_for_byte_range(start_byte, start_byte + 1)?;
+    let end_node = root.named_descendant_for_byte_range(end_byte, end_byte + 1)?;
     let sp = start_node.parent()?;
     let ep = end_node.parent()?;
     if sp.kind() != "member_expression" || ep.kind() != "member_expression" {
@@ -69,17 +71,11 @@ pub async fn get_function_call_action(
     let start_object_node = sp.child_by_field_name("object");
     let end_object_node = ep.child_by_field_name("object");
     if let (Some(start), Some(_)) = (start_object_node, end_object_node) {
-        let replace_range = generate_lsp_range(
-            ep.start_position().row as u32,
-            ep.start_position().column as u32,
-            ep.end_position().row as u32,
-            ep.end_position().column as u32,
-        );
+        let replace_range = encoding.node_range(rope, ep);
         // debug!("sp_parent: {}, ep_parent: {}", sp.kind(), ep.kind());
-        let document_source = document.rope.to_string();
-        let object_source_code = &document_source[start.byte_range()];
+        let object_source_code = &source[start.byte_range()];
 
-        let function = &document_source[start_node.start_byte()..end_node.end_byte()];
+        let function = &source[start_node.start_byte()..end_node.end_byte()];
 
         let replaced_code = format!("{}({})", function, object_source_code);
 
@@ -103,15 +99,416 @@ pub async fn get_function_call_action(
     Some(ret)
 }
 
-const IDENTIFIER_QUERY_PATTERN: &str = r#"(identifier) @a"#;
-const JSX_EXPRESSION_QUERY_PATTERN: &str = r#"(jsx_expression) @a"#;
+pub async fn get_extract_component_action(
+    back_end: &Backend,
+    params: CodeActionParams,
+) -> Option<CodeActionResponse> {
+    let snapshot = back_end.get_snapshot(&params.text_document.uri).await?;
+    let DocumentSnapshot {
+        rope,
+        source,
+        language,
+        tree,
+        ..
+    } = &*snapshot;
+    let encoding = back_end.position_encoding();
+    let indent_style = back_end.indent_style(&params.text_document.uri).await;
+    let duration = Instant::now();
+    let root = tree.root_node();
+    let range = params.range;
+    let start_byte = encoding.position_to_byte(rope, range.start)?;
+    let end_byte = encoding.position_to_byte(rope, range.end)?;
+
+    let element = selected_element(root, source, start_byte, end_byte)?;
+    // the extracted component is inserted after the top level statement
+    let mut top_level_statement = element;
+    while let Some(parent) = top_level_statement.parent() {
+        if parent.kind() == "program" {
+            break;
+        }
+        top_level_statement = parent;
+    }
+    if top_level_statement == element {
+        return None;
+    }
+
+    let key = key_attribute(element, source);
+    let registry = &back_end.query_registry;
+    let data = ExtractComponentData {
+        jsx_element_range: encoding.node_range(rope, element),
+        identifier_node_list: get_free_identifier_list(
+            registry.get(*language, QueryName::JsxExpression)?,
+            registry.get(*language, QueryName::Identifier)?,
+            source.as_bytes(),
+            element,
+            key.map(|key| key.byte_range()),
+            |node| encoding.node_range(rope, node),
+        ),
+        function_name: get_function_name_from_program(
+            registry.get(*language, QueryName::FunctionLikeDeclaration)?,
+            source.as_bytes(),
+            root,
+        ),
+        key: key.map(|key| source[key.byte_range()].to_string()),
+    };
+    let component_code = generate_component_code(
+        &data,
+        source,
+        element,
+        language.is_typescript(),
+        indent_style,
+    );
+    let insert_position = encoding.byte_to_position(rope, top_level_statement.end_byte());
+    let insert_range = Range::new(insert_position, insert_position);
+
+    let mut ret = CodeActionResponse::new();
+    ret.push(CodeActionOrCommand::CodeAction(CodeAction {
+        title: format!("extract to component -> {}", data.function_name),
+        kind: Some(CodeActionKind::REFACTOR_EXTRACT),
+        diagnostics: None,
+        edit: Some(data.to_workspace_edit(
+            params.text_document.uri,
+            insert_range,
+            format!("\n\n{}", component_code),
+        )),
+        command: None,
+        is_preferred: Some(false),
+        disabled: None,
+        data: None,
+    }));
+
+    debug!("extract-component: {:?}", duration.elapsed());
+
+    Some(ret)
+}
+
+impl ExtractComponentData {
+    fn to_workspace_edit(
+        &self,
+        uri: Url,
+        insert_range: Range,
+        component_code: String,
+    ) -> WorkspaceEdit {
+        let props = self.identifier_node_list.iter().fold(
+            self.key
+                .as_ref()
+                .map(|key| format!(" {}", key))
+                .unwrap_or_default(),
+            |mut props, id| {
+                let _ = write!(props, " {}={{{}}}", id.name, id.name);
+                props
+            },
+        );
+        let mut changes = HashMap::new();
+        changes.insert(
+            uri,
+            vec![
+                TextEdit::new(
+                    self.jsx_element_range,
+                    format!("<{}{} />", self.function_name, props),
+                ),
+                TextEdit::new(insert_range, component_code),
+            ],
+        );
+        WorkspaceEdit::new(changes)
+    }
+}
+
+fn generate_component_code(
+    data: &ExtractComponentData,
+    source: &str,
+    element: Node,
+    is_typescript: bool,
+    indent_style: IndentStyle,
+) -> String {
+    let name_list = data
+        .identifier_node_list
+        .iter()
+        .map(|id| id.name.as_str())
+        .collect::<Vec<_>>();
+    let props = if name_list.is_empty() {
+        String::new()
+    } else if is_typescript {
+        format!(
+            "{{ {} }}: {{ {} }}",
+            name_list.join(", "),
+            name_list
+                .iter()
+                .map(|name| format!("{}: any", name))
+                .collect::<Vec<_>>()
+                .join("; ")
+        )
+    } else {
+        format!("{{ {} }}", name_list.join(", "))
+    };
+
+    // the following lines of the element are reindented relative to the line it starts
+    let line_start = source[..element.start_byte()]
+        .rfind('\n')
+        .map(|i| i + 1)
+        .unwrap_or(0);
+    let base_indent = source[line_start..]
+        .chars()
+        .take_while(|c| *c == ' ' || *c == '\t')
+        .collect::<String>();
+    // `key` is left on the call site
+    let element_source = match key_attribute(element, source) {
+        Some(key) => {
+            let key_start = source[..key.start_byte()].trim_end().len();
+            format!(
+                "{}{}",
+                &source[element.start_byte()..key_start],
+                &source[key.end_byte()..element.end_byte()]
+            )
+        }
+        None => source[element.byte_range()].to_string(),
+    };
+    let element_text = element_source
+        .lines()
+        .map(|line| line.strip_prefix(base_indent.as_str()).unwrap_or(line))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let unit = indent_style.unit();
+    let body_indent = unit.repeat(2);
+    let body = reindent(
+        &element_text,
+        space_unit(&element_text),
+        &body_indent,
+        indent_style,
+    );
+    format!(
+        "function {}({}) {{\n{}return (\n{}{}\n{});\n}}",
+        data.function_name, props, unit, body_indent, body, unit
+    )
+}
+
+/// the element whose tag is under the cursor, or which is exactly selected, an element only
+/// containing the cursor or the selection is not offered
+fn selected_element<'a>(
+    root: Node<'a>,
+    source: &str,
+    start_byte: usize,
+    end_byte: usize,
+) -> Option<Node<'a>> {
+    if start_byte == end_byte {
+        let mut node = root.descendant_for_byte_range(start_byte, start_byte)?;
+        loop {
+            match node.kind() {
+                "jsx_opening_element" | "jsx_closing_element" => return node.parent(),
+                "jsx_self_closing_element" => return Some(node),
+                // in the attributes or the children
+                "jsx_attribute" | "jsx_expression" | "jsx_text" | "jsx_element" => return None,
+                _ => node = node.parent()?,
+            }
+        }
+    }
+    let selected = &source[start_byte..end_byte];
+    let start = start_byte + selected.len() - selected.trim_start().len();
+    let end = end_byte - (selected.len() - selected.trim_end().len());
+    if start >= end {
+        return None;
+    }
+    let node = root.named_descendant_for_byte_range(start, end)?;
+    let is_element = matches!(node.kind(), "jsx_element" | "jsx_self_closing_element");
+    (is_element && node.byte_range() == (start..end)).then_some(node)
+}
+
+/// the `key` attribute of the element
+fn key_attribute<'a>(element: Node<'a>, source: &str) -> Option<Node<'a>> {
+    let tag = match element.kind() {
+        "jsx_element" => element.child_by_field_name("open_tag")?,
+        _ => element,
+    };
+    let mut cursor = tag.walk();
+    let key = tag.named_children(&mut cursor).find(|attribute| {
+        attribute.kind() == "jsx_attribute"
+            && attribute
+                .named_child(0)
+                .is_some_and(|name| &source[name.byte_range()] == "key")
+    });
+    key
+}
+
+/// identifiers referenced in the element but declared in the enclosing functions,
+/// they become props of the extracted component, except those only in `key_range`
+fn get_free_identifier_list(
+    jsx_expression_query: &Query,
+    identifier_query: &Query,
+    source: &[u8],
+    element: Node,
+    key_range: Option<std::ops::Range<usize>>,
+    node_range: impl Fn(Node) -> Range,
+) -> Vec<IdentifierNode> {
+    let mut scope_name_set = HashSet::new();
+    let mut cur = element;
+    while let Some(parent) = cur.parent() {
+        collect_scope_declaration(parent, source, &mut scope_name_set);
+        cur = parent;
+    }
+    let mut inner_name_set = HashSet::new();
+    collect_inner_binding(element, source, &mut inner_name_set);
+
+    let mut cursor = QueryCursor::new();
+    let mut expression_range_list = vec![];
+    let mut jsx_expression_matches = cursor.matches(jsx_expression_query, element, source);
+    while let Some(item) = jsx_expression_matches.next() {
+        for cap in item.captures {
+            expression_range_list.push(cap.node.byte_range());
+        }
+    }
+
+    let mut cursor = QueryCursor::new();
+    let mut visited = HashSet::new();
+    let mut identifier_node_list = vec![];
+    let mut identifier_matches = cursor.matches(identifier_query, element, source);
+    while let Some(item) = identifier_matches.next() {
+        for cap in item.captures {
+            let node = cap.node;
+            let name = match node.utf8_text(source) {
+                Ok(name) => name,
+                Err(_) => continue,
+            };
+            // the key stays on the call site, its identifiers are not props
+            let in_key = key_range.as_ref().is_some_and(|range| {
+                range.start <= node.start_byte() && node.end_byte() <= range.end
+            });
+            if in_key {
+                continue;
+            }
+            let in_expression = expression_range_list
+                .iter()
+                .any(|range| range.start <= node.start_byte() && node.end_byte() <= range.end);
+            // capitalized tag name is a reference of a component, lowercase one is intrinsic element
+            let is_component_tag = node.parent().is_some_and(|parent| {
+                matches!(
+                    parent.kind(),
+                    "jsx_opening_element" | "jsx_closing_element" | "jsx_self_closing_element"
+                )
+            }) && name.is_pascal_case();
+            if !(in_expression || is_component_tag)
+                || !scope_name_set.contains(name)
+                || inner_name_set.contains(name)
+                || !visited.insert(name.to_string())
+            {
+                continue;
+            }
+            identifier_node_list.push(IdentifierNode {
+                start: node.start_byte(),
+                end: node.end_byte(),
+                range: node_range(node),
+                name: name.to_string(),
+            });
+        }
+    }
+    identifier_node_list
+}
+
+/// names declared directly in the scope of `node`, module level names are skipped since the
+/// extracted component could still reach them
+fn collect_scope_declaration(node: Node, source: &[u8], name_set: &mut HashSet<String>) {
+    match node.kind() {
+        "arrow_function"
+        | "function_expression"
+        | "function_declaration"
+        | "generator_function"
+        | "generator_function_declaration"
+        | "method_definition" => {
+            if let Some(parameter) = node.child_by_field_name("parameter") {
+                collect_binding_name(parameter, source, name_set);
+            }
+            if let Some(parameters) = node.child_by_field_name("parameters") {
+                collect_binding_name(parameters, source, name_set);
+            }
+        }
+        "statement_block" | "switch_case" | "switch_default" => {
+            for i in 0..node.named_child_count() {
+                collect_declaration_name(node.named_child(i).unwrap(), source, name_set);
+            }
+        }
+        "for_statement" | "for_in_statement" => {
+            for field in ["initializer", "left"] {
+                if let Some(child) = node.child_by_field_name(field) {
+                    collect_declaration_name(child, source, name_set);
+                    if node.kind() == "for_in_statement" {
+                        collect_binding_name(child, source, name_set);
+                    }
+                }
+            }
+        }
+        "catch_clause" => {
+            if let Some(parameter) = node.child_by_field_name("parameter") {
+                collect_binding_name(parameter, source, name_set);
+            }
+        }
+        _ => {}
+    }
+}
 
-fn get_function_name_from_program<'b>(lang: Language, source: &[u8], root: Node<'b>) -> String {
-    let jsx_query = Query::new(&lang, FUNCTION_LIKE_DECLARATION).unwrap();
+/// names bound anywhere inside `node`, e.g. `items.map(item => <li>{item}</li>)`
+fn collect_inner_binding(node: Node, source: &[u8], name_set: &mut HashSet<String>) {
+    collect_scope_declaration(node, source, name_set);
+    for i in 0..node.named_child_count() {
+        collect_inner_binding(node.named_child(i).unwrap(), source, name_set);
+    }
+}
 
+fn collect_declaration_name(statement: Node, source: &[u8], name_set: &mut HashSet<String>) {
+    match statement.kind() {
+        "lexical_declaration" | "variable_declaration" => {
+            for i in 0..statement.named_child_count() {
+                if let Some(name) = statement
+                    .named_child(i)
+                    .unwrap()
+                    .child_by_field_name("name")
+                {
+                    collect_binding_name(name, source, name_set);
+                }
+            }
+        }
+        "function_declaration" | "generator_function_declaration" | "class_declaration" => {
+            if let Some(name) = statement.child_by_field_name("name") {
+                collect_binding_name(name, source, name_set);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// identifiers of a binding pattern, default values and type annotations are skipped
+fn collect_binding_name(pattern: Node, source: &[u8], name_set: &mut HashSet<String>) {
+    match pattern.kind() {
+        "identifier" | "shorthand_property_identifier_pattern" => {
+            if let Ok(name) = pattern.utf8_text(source) {
+                name_set.insert(name.to_string());
+            }
+        }
+        "assignment_pattern" | "object_assignment_pattern" => {
+            if let Some(left) = pattern.child_by_field_name("left") {
+                collect_binding_name(left, source, name_set);
+            }
+        }
+        "pair_pattern" => {
+            if let Some(value) = pattern.child_by_field_name("value") {
+                collect_binding_name(value, source, name_set);
+            }
+        }
+        "type_annotation" => {}
+        _ => {
+            for i in 0..pattern.named_child_count() {
+                collect_binding_name(pattern.named_child(i).unwrap(), source, name_set);
+            }
+        }
+    }
+}
+
+fn get_function_name_from_program<'b>(
+    function_like_query: &Query,
+    source: &[u8],
+    root: Node<'b>,
+) -> String {
     let mut cursor = QueryCursor::new();
     // pretty_print(&source, node, 0);
-    let mut jsx_matches = cursor.matches(&jsx_query, root, source);
+    let mut jsx_matches = cursor.matches(function_like_query, root, source);
     let mut id_set = HashSet::new();
     while let Some(item) = jsx_matches.next() {
         for cap in item.captures {
@@ -130,3 +527,111 @@ fn get_function_name_from_program<'b>(lang: Language, source: &[u8], root: Node<
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::language::LanguageKind;
+
+    /// the component extracted from the first `<p` in `code`
+    fn component_code(code: &str, name_list: &[&str], indent_style: IndentStyle) -> String {
+        let language = LanguageKind::TypeScriptReact;
+        let tree = language
+            .parse_with(&mut |byte_index, _| &code.as_bytes()[byte_index..], None)
+            .unwrap();
+        let start_byte = code.find("<p").unwrap();
+        let mut element = tree
+            .root_node()
+            .descendant_for_byte_range(start_byte, start_byte)
+            .unwrap();
+        while element.kind() != "jsx_element" {
+            element = element.parent().unwrap();
+        }
+        let data = ExtractComponentData {
+            jsx_element_range: Range::default(),
+            identifier_node_list: name_list
+                .iter()
+                .map(|name| IdentifierNode {
+                    start: 0,
+                    end: 0,
+                    range: Range::default(),
+                    name: name.to_string(),
+                })
+                .collect(),
+            function_name: "Component".into(),
+            key: None,
+        };
+        generate_component_code(&data, code, element, true, indent_style)
+    }
+
+    /// the kind and text of the element offered for the cursor or the selection of `‸`
+    fn selected(code: &str) -> Option<(String, String)> {
+        let mut byte_list = code.match_indices('‸').map(|(i, _)| i).collect::<Vec<_>>();
+        if byte_list.len() == 2 {
+            byte_list[1] -= '‸'.len_utf8();
+        }
+        let code = code.replace('‸', "");
+        let (start_byte, end_byte) = (byte_list[0], *byte_list.last().unwrap());
+        let tree = LanguageKind::TypeScriptReact
+            .parse_with(&mut |byte_index, _| &code.as_bytes()[byte_index..], None)
+            .unwrap();
+        selected_element(tree.root_node(), &code, start_byte, end_byte)
+            .map(|node| (node.kind().to_string(), code[node.byte_range()].to_string()))
+    }
+
+    #[test]
+    fn component_indented_by_style() {
+        let code = "function App() {\n  return (\n    <div>\n      <p>\n        {a}\n      </p>\n    </div>\n  );\n}";
+        assert_eq!(
+            component_code(code, &["a"], IndentStyle::default()),
+            "function Component({ a }: { a: any }) {\n    return (\n        <p>\n            {a}\n        </p>\n    );\n}"
+        );
+        let tab = IndentStyle {
+            insert_spaces: false,
+            tab_size: 4,
+        };
+        assert_eq!(
+            component_code(code, &[], tab),
+            "function Component() {\n\treturn (\n\t\t<p>\n\t\t\t{a}\n\t\t</p>\n\t);\n}"
+        );
+    }
+
+    #[test]
+    fn offered_on_element_boundary() {
+        let element = Some(("jsx_element".to_string(), "<p>{a}</p>".to_string()));
+        assert_eq!(selected("<div>‸<p>{a}</p></div>"), element);
+        assert_eq!(selected("<div><‸p>{a}</p></div>"), element);
+        assert_eq!(selected("<div><p>{a}</‸p></div>"), element);
+        assert_eq!(selected("<div> ‸<p>{a}</p>‸ </div>"), element);
+        assert_eq!(
+            selected("<div><br ‸/></div>"),
+            Some(("jsx_self_closing_element".into(), "<br />".into()))
+        );
+        // inside the children or the attributes
+        assert_eq!(selected("<div><p>{‸a}</p></div>"), None);
+        assert_eq!(selected("<div><p>te‸xt</p></div>"), None);
+        assert_eq!(selected("<div><p id=\"‸a\">b</p></div>"), None);
+        assert_eq!(selected("<div><p>{‸a}</p>‸</div>"), None);
+    }
+
+    #[test]
+    fn key_stays_on_call_site() {
+        let code = "function App() {\n  return list.map((item) => (\n    <p key={item.id} id={id}>\n      {item.name}\n    </p>\n  ));\n}";
+        assert_eq!(
+            component_code(code, &["item", "id"], IndentStyle::default()),
+            "function Component({ item, id }: { item: any; id: any }) {\n    return (\n        <p id={id}>\n            {item.name}\n        </p>\n    );\n}"
+        );
+        let data = ExtractComponentData {
+            jsx_element_range: Range::default(),
+            identifier_node_list: vec![],
+            function_name: "Component".into(),
+            key: Some("key={item.id}".into()),
+        };
+        let uri = Url::parse("file:///a.tsx").unwrap();
+        let edit = data.to_workspace_edit(uri.clone(), Range::default(), String::new());
+        assert_eq!(
+            edit.changes.unwrap()[&uri][0].new_text,
+            "<Component key={item.id} />"
+        );
+    }
+}
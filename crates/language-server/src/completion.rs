@@ -1,67 +1,508 @@
-use inflector::Inflector;
+use std::collections::HashMap;
+
+use ropey::Rope;
+use serde::{Deserialize, Serialize};
 use streaming_iterator::StreamingIterator;
-use tokio::sync::MutexGuard;
 use tower_lsp::lsp_types::*;
-use tree_sitter::{Language, Node, Parser, Query, QueryCursor, Tree};
-
-use crate::query_pattern::REACT_NAME_SPACE_IMPORT;
-
-pub fn get_react_completion(
-    name: &str,
-    source: &str,
-    replace_range: &Range,
-    tree: &Tree,
-    parser: MutexGuard<Parser>,
-) -> Vec<CompletionItem> {
-    let mut result = vec![];
-    // let mut identifier_list = vec![];
-    let function_call = if let Some(node) = get_react_import_node(
-        &parser.language().unwrap(),
-        source.as_bytes(),
-        tree.root_node(),
-    ) {
-        if let Ok(id) = node.utf8_text(source.as_bytes()) {
-            format!("{}.useState", id)
+use tree_sitter::{Node, Query, QueryCursor};
+
+use crate::import::DocumentImport;
+use crate::indent::IndentStyle;
+use crate::language::LanguageKind;
+use crate::position::PositionEncoding;
+use crate::receiver::{EnclosingRange, EnclosingScope};
+use crate::template::{
+    escape_snippet_text, snippet_to_text, CompiledTemplate, RequiredImport, TemplateSource,
+    TemplateTarget,
+};
+
+/// Completion features of the client
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CompletionCapability {
+    pub insert_replace_support: bool,
+    /// `InsertTextMode::AS_IS` can be set on items, snippets are indented by the server then
+    pub as_is_support: bool,
+    /// the client prepends the indentation of current line to every line of the snippet
+    pub adjusts_indentation: bool,
+}
+
+impl CompletionCapability {
+    pub fn new(capabilities: &ClientCapabilities) -> Self {
+        let completion = capabilities
+            .text_document
+            .as_ref()
+            .and_then(|text_document| text_document.completion.as_ref());
+        let completion_item = completion.and_then(|completion| completion.completion_item.as_ref());
+        let as_is_support = completion_item
+            .and_then(|completion_item| completion_item.insert_text_mode_support.as_ref())
+            .is_some_and(|support| support.value_set.contains(&InsertTextMode::AS_IS));
+        Self {
+            insert_replace_support: completion_item
+                .and_then(|completion_item| completion_item.insert_replace_support)
+                .unwrap_or(false),
+            as_is_support,
+            adjusts_indentation: !as_is_support
+                && completion.and_then(|completion| completion.insert_text_mode)
+                    == Some(InsertTextMode::ADJUST_INDENTATION),
+        }
+    }
+}
+
+/// What the postfix items of a completion share
+#[derive(Debug, Clone, Default)]
+pub struct PostfixEnvironment {
+    pub capability: CompletionCapability,
+    pub indent_style: IndentStyle,
+    pub document_import: DocumentImport,
+    pub react_import: ReactImport,
+}
+
+/// Where a postfix item applies, every item replaces `receiver.keyword` with a single edit, so
+/// no edit overlaps another one, except wrapping templates, e.g. of `statement`, which replace
+/// the `.keyword` and insert around what they wrap with additional edits
+#[derive(Debug, Clone)]
+pub struct PostfixRange<'a> {
+    /// from the edit start to the cursor
+    pub insert: Range,
+    /// from the edit start to the end of the keyword, which may continue after the cursor
+    pub replace: Range,
+    /// the text from the edit start to the keyword, the label is appended to it for filtering
+    pub filter_prefix: String,
+    /// the receiver on previous lines, deleted by itself since the edit of an item must be in
+    /// a single line
+    pub receiver_delete: Option<Range>,
+    /// indentation of the line the receiver starts at, where the body of snippets is
+    /// indented from
+    pub indent: String,
+    /// the statement containing the receiver
+    pub statement: Option<WrapRange>,
+    /// the jsx element receiver and the elements before it
+    pub jsx_sibling: Option<WrapRange>,
+    /// where `async ` goes to make the enclosing function async
+    pub async_insert: Option<Position>,
+    pub environment: &'a PostfixEnvironment,
+}
+
+/// Where a wrapping template puts the parts before and after what it wraps, the receiver
+/// stays where it is and the edit of the item only removes the postfix
+#[derive(Debug, Clone)]
+pub struct WrapRange {
+    pub start: Position,
+    pub end: Position,
+    /// from the dot, or the `?` of `?.`, to the cursor
+    pub postfix_insert: Range,
+    /// from the dot to the end of the keyword
+    pub postfix_replace: Range,
+    /// the text of `.` or `?.`, the label is appended to it for filtering
+    pub postfix_filter_prefix: String,
+    /// the wrapped text after the keyword if it ends in the line of the cursor, replaced by
+    /// the edit of the item along with the postfix
+    pub rest: Option<String>,
+    /// starts of the lines indented a level more
+    pub line_start_list: Vec<Position>,
+    /// indentation of the line the wrapped text starts at
+    pub indent: String,
+    /// the wrapped text without the postfix
+    pub text: String,
+}
+
+/// `data` of postfix items, what `completionItem/resolve` needs to render the preview
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PostfixResolveData {
+    pub language_id: String,
+    pub indent: String,
+    /// the indentation the client prepends to the lines after the first
+    pub client_indent: String,
+    /// see `TemplateSource`
+    pub source: String,
+    /// the code of a wrapping template with what it wraps, whose edit of the item only
+    /// removes the postfix
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preview: Option<String>,
+}
+
+impl<'a> PostfixRange<'a> {
+    pub fn new(
+        rope: &Rope,
+        encoding: PositionEncoding,
+        receiver_start_byte: usize,
+        dot_byte: usize,
+        cursor_byte: usize,
+        scope: &EnclosingScope,
+        environment: &'a PostfixEnvironment,
+    ) -> Self {
+        let keyword_end_char = rope
+            .chars_at(rope.byte_to_char(cursor_byte))
+            .take_while(|c| c.is_alphanumeric() || matches!(c, '_' | '$'))
+            .count()
+            + rope.byte_to_char(cursor_byte);
+        let cursor = encoding.byte_to_position(rope, cursor_byte);
+        let keyword_end = encoding.char_to_position(rope, keyword_end_char);
+        let receiver_start = encoding.byte_to_position(rope, receiver_start_byte);
+        let (start_byte, receiver_delete) = if receiver_start.line == cursor.line {
+            (receiver_start_byte, None)
         } else {
-            "useState".to_string()
+            (
+                dot_byte,
+                Some(Range::new(
+                    receiver_start,
+                    encoding.byte_to_position(rope, dot_byte),
+                )),
+            )
+        };
+        let start = encoding.byte_to_position(rope, start_byte);
+        let line_indent = |line: u32| -> String {
+            rope.line(line as usize)
+                .chars()
+                .take_while(|c| *c == ' ' || *c == '\t')
+                .collect()
+        };
+        let keyword_end_byte = rope.char_to_byte(keyword_end_char);
+        let postfix_start_byte = if dot_byte > 0 && rope.byte(dot_byte - 1) == b'?' {
+            dot_byte - 1
+        } else {
+            dot_byte
+        };
+        let postfix_start = encoding.byte_to_position(rope, postfix_start_byte);
+        let wrap_range = |enclosing: &EnclosingRange| {
+            // jsx siblings end before the postfix, which is removed along with them
+            let end_byte = enclosing.byte_range.end.max(keyword_end_byte);
+            let start = encoding.byte_to_position(rope, enclosing.byte_range.start);
+            let end = encoding.byte_to_position(rope, end_byte);
+            WrapRange {
+                start,
+                end,
+                postfix_insert: Range::new(postfix_start, cursor),
+                postfix_replace: Range::new(postfix_start, keyword_end),
+                postfix_filter_prefix: rope
+                    .byte_slice(postfix_start_byte..dot_byte + 1)
+                    .to_string(),
+                rest: (end.line == cursor.line)
+                    .then(|| rope.byte_slice(keyword_end_byte..end_byte).to_string()),
+                line_start_list: enclosing
+                    .line_start_list
+                    .iter()
+                    .map(|byte| encoding.byte_to_position(rope, *byte))
+                    .collect(),
+                indent: line_indent(start.line),
+                text: rope
+                    .byte_slice(enclosing.byte_range.start..postfix_start_byte)
+                    .to_string()
+                    + &rope.byte_slice(keyword_end_byte..end_byte).to_string(),
+            }
+        };
+        Self {
+            insert: Range::new(start, cursor),
+            replace: Range::new(start, keyword_end),
+            filter_prefix: rope.byte_slice(start_byte..dot_byte + 1).to_string(),
+            receiver_delete,
+            indent: line_indent(receiver_start.line),
+            statement: scope.statement.as_ref().map(wrap_range),
+            jsx_sibling: scope.jsx_sibling.as_ref().map(wrap_range),
+            async_insert: scope
+                .async_insert_byte
+                .map(|byte| encoding.byte_to_position(rope, byte)),
+            environment,
         }
-    } else {
-        "useState".to_string()
+    }
+
+    /// what snippet bodies are indented with besides `indent_style`, the client does it
+    /// if it adjusts the indentation
+    pub fn base_indent(&self) -> &str {
+        if self.environment.capability.adjusts_indentation {
+            ""
+        } else {
+            &self.indent
+        }
+    }
+
+    /// make `item` replace the postfix with `template` and import the names it requires, the
+    /// preview is rendered on resolve. A wrapping template is not applicable without what it
+    /// wraps
+    pub fn apply(
+        &self,
+        item: &mut CompletionItem,
+        template: &CompiledTemplate,
+        receiver: &str,
+        language: LanguageKind,
+        source: TemplateSource,
+    ) -> bool {
+        let indent_style = self.environment.indent_style;
+        let (rename_list, required_import_list) = self
+            .environment
+            .react_import
+            .resolve(&template.required_import_list);
+        let template = &*template.renamed(&rename_list);
+        if !rename_list.is_empty() {
+            item.detail = Some(
+                template
+                    .description
+                    .clone()
+                    .unwrap_or_else(|| template.code.clone()),
+            );
+        }
+        let mut additional_text_edits = self
+            .environment
+            .document_import
+            .edit_list(&required_import_list);
+        let mut preview = None;
+        let (new_text, insert, replace, filter_prefix) = match template.target {
+            TemplateTarget::Expression => {
+                if let Some(range) = self.receiver_delete {
+                    additional_text_edits.push(TextEdit::new(range, String::new()));
+                }
+                (
+                    template.expand(receiver, self.base_indent(), indent_style),
+                    self.insert,
+                    self.replace,
+                    &self.filter_prefix,
+                )
+            }
+            TemplateTarget::Statement | TemplateTarget::JsxSiblings => {
+                let wrap_range = match template.target {
+                    TemplateTarget::Statement => &self.statement,
+                    _ => &self.jsx_sibling,
+                };
+                let wrap_range = match wrap_range {
+                    Some(wrap_range) => wrap_range,
+                    None => return false,
+                };
+                let (prefix, suffix) = template.expand_around(&wrap_range.indent, indent_style);
+                let unit = indent_style.unit();
+                preview = Some(format!(
+                    "{}{}{}{}",
+                    wrap_range.indent,
+                    snippet_to_text(&prefix),
+                    wrap_range.text.replace('\n', &format!("\n{}", unit)),
+                    snippet_to_text(&suffix)
+                ));
+                additional_text_edits.push(TextEdit::new(
+                    Range::new(wrap_range.start, wrap_range.start),
+                    snippet_to_text(&prefix),
+                ));
+                // the line of `foo\n.try` starting with the postfix is left as is, it would
+                // touch the edit of the item
+                for line_start in wrap_range
+                    .line_start_list
+                    .iter()
+                    .filter(|line_start| **line_start != wrap_range.postfix_insert.start)
+                {
+                    additional_text_edits.push(TextEdit::new(
+                        Range::new(*line_start, *line_start),
+                        indent_style.unit(),
+                    ));
+                }
+                let mut new_text = String::new();
+                match &wrap_range.rest {
+                    Some(rest) => {
+                        // the client indents the edit of the item if it adjusts the
+                        // indentation, but not the additional edits
+                        let item_indent = if self.environment.capability.adjusts_indentation {
+                            ""
+                        } else {
+                            &wrap_range.indent
+                        };
+                        new_text.push_str(&escape_snippet_text(rest));
+                        new_text.push_str(&template.expand_around(item_indent, indent_style).1);
+                        // the rest after the cursor is replaced in both modes
+                        let range = Range::new(wrap_range.postfix_insert.start, wrap_range.end);
+                        (new_text, range, range, &wrap_range.postfix_filter_prefix)
+                    }
+                    None => {
+                        additional_text_edits.push(TextEdit::new(
+                            Range::new(wrap_range.end, wrap_range.end),
+                            snippet_to_text(&suffix),
+                        ));
+                        (
+                            new_text,
+                            wrap_range.postfix_insert,
+                            wrap_range.postfix_replace,
+                            &wrap_range.postfix_filter_prefix,
+                        )
+                    }
+                }
+            }
+        };
+        if template.mark_async {
+            if let Some(position) = self.async_insert {
+                additional_text_edits.push(TextEdit::new(
+                    Range::new(position, position),
+                    "async ".to_string(),
+                ));
+            }
+        }
+        item.data = serde_json::to_value(PostfixResolveData {
+            language_id: language.language_id().to_string(),
+            indent: self.indent.clone(),
+            client_indent: self.indent[self.base_indent().len()..].to_string(),
+            source: source.to_string(),
+            preview,
+        })
+        .ok();
+        if self.environment.capability.as_is_support {
+            item.insert_text_mode = Some(InsertTextMode::AS_IS);
+        }
+        item.filter_text = Some(format!("{}{}", filter_prefix, item.label));
+        item.text_edit = Some(if self.environment.capability.insert_replace_support {
+            CompletionTextEdit::InsertAndReplace(InsertReplaceEdit {
+                new_text,
+                insert,
+                replace,
+            })
+        } else {
+            CompletionTextEdit::Edit(TextEdit::new(insert, new_text))
+        });
+        item.insert_text = None;
+        item.additional_text_edits =
+            (!additional_text_edits.is_empty()).then_some(additional_text_edits);
+        true
+    }
+}
+
+/// fill the documentation of a postfix item with the code it inserts, in a fenced code block
+/// of the document language
+pub fn resolve_postfix_item(mut item: CompletionItem) -> CompletionItem {
+    let data = match item
+        .data
+        .clone()
+        .and_then(|data| serde_json::from_value::<PostfixResolveData>(data).ok())
+    {
+        Some(data) => data,
+        None => return item,
+    };
+    let new_text = match &item.text_edit {
+        Some(CompletionTextEdit::Edit(edit)) => &edit.new_text,
+        Some(CompletionTextEdit::InsertAndReplace(edit)) => &edit.new_text,
+        None => return item,
+    };
+    let preview = match &data.preview {
+        Some(preview) => preview.clone(),
+        None => snippet_to_text(new_text)
+            .lines()
+            .enumerate()
+            .map(|(i, line)| {
+                let indent = if i == 0 {
+                    &data.indent
+                } else {
+                    &data.client_indent
+                };
+                format!("{}{}", indent, line)
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
     };
+    let fence = LanguageKind::from_language_id(&data.language_id)
+        .map_or("typescript", |language| language.code_fence());
+    item.documentation = Some(Documentation::MarkupContent(MarkupContent {
+        kind: MarkupKind::Markdown,
+        value: format!("```{}\n{}\n```\n{}", fence, preview, data.source),
+    }));
+    item
+}
 
-    let mut item = CompletionItem::new_simple(
-        "state".to_string(),
-        format!("const [<expr>, <expr>] = {}()", function_call),
-    );
-    item.kind = Some(CompletionItemKind::SNIPPET);
-    let replace_string = format!(
-        "const [{}, set{}] = {}(${{0}})",
-        name,
-        name.to_pascal_case(),
-        function_call
-    );
-    item.documentation = Some(Documentation::String(replace_string.clone()));
-    item.insert_text = Some(replace_string);
-    item.insert_text_format = Some(InsertTextFormat::SNIPPET);
-    item.additional_text_edits = Some(vec![TextEdit::new(replace_range.clone(), "".into())]);
-    result.push(item);
-    result
+/// Local names of react hooks in a document, from the imports of `react`, `preact/hooks` or
+/// `preact/compat`
+#[derive(Debug, Clone, Default)]
+pub struct ReactImport {
+    /// the first module imported, where missing hooks are imported from
+    module: Option<String>,
+    /// `React` of `import * as React from 'react'` or `import React from 'react'`
+    namespace: Option<String>,
+    /// imported name to local name, e.g. `useState` to `useS` of
+    /// `import { useState as useS } from 'react'`
+    named_map: HashMap<String, String>,
 }
 
-fn get_react_import_node<'a>(
-    lang: &Language,
-    source: &[u8],
-    root: Node<'a>, // identifier_node_list: &'b mut Vec<Node<'a>>,
-) -> Option<Node<'a>> {
-    let jsx_expression_query = Query::new(&lang, &REACT_NAME_SPACE_IMPORT).unwrap();
+impl ReactImport {
+    pub fn new(react_import_query: Option<&Query>, source: &str, root: Node) -> Self {
+        let mut react_import = ReactImport::default();
+        let query = match react_import_query {
+            Some(query) => query,
+            None => return react_import,
+        };
+        let source = source.as_bytes();
+        let mut cursor = QueryCursor::new();
+        let mut matches = cursor.matches(query, root, source);
+        while let Some(item) = matches.next() {
+            let (mut clause, mut module) = (None, None);
+            for capture in item.captures {
+                match query.capture_names()[capture.index as usize] {
+                    "clause" => clause = Some(capture.node),
+                    "source" => module = capture.node.utf8_text(source).ok(),
+                    _ => {}
+                }
+            }
+            let (clause, module) = match (clause, module) {
+                (Some(clause), Some(module)) => (clause, module),
+                _ => continue,
+            };
+            react_import.module.get_or_insert(module.to_string());
+            let text = |node: Node| node.utf8_text(source).unwrap_or_default().to_string();
+            let mut clause_cursor = clause.walk();
+            for child in clause.named_children(&mut clause_cursor) {
+                match child.kind() {
+                    // `preact/hooks` has no default export
+                    "identifier" if module != "preact/hooks" => {
+                        react_import.namespace.get_or_insert(text(child));
+                    }
+                    "namespace_import" => {
+                        if let Some(namespace) = child.named_child(0) {
+                            react_import.namespace.get_or_insert(text(namespace));
+                        }
+                    }
+                    "named_imports" => {
+                        let mut specifier_cursor = child.walk();
+                        for specifier in child.named_children(&mut specifier_cursor) {
+                            let name = match specifier.child_by_field_name("name") {
+                                Some(name) => text(name),
+                                None => continue,
+                            };
+                            let alias = specifier
+                                .child_by_field_name("alias")
+                                .map_or_else(|| name.clone(), text);
+                            react_import.named_map.entry(name).or_insert(alias);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        react_import
+    }
 
-    let mut cursor = QueryCursor::new();
+    /// how `hook` is called, `None` if it is not imported
+    pub fn hook_call(&self, hook: &str) -> Option<String> {
+        self.named_map.get(hook).cloned().or_else(|| {
+            self.namespace
+                .as_ref()
+                .map(|namespace| format!("{}.{}", namespace, hook))
+        })
+    }
 
-    let mut jsx_expression_matches = cursor.matches(&jsx_expression_query, root, source);
-    while let Some(item) = jsx_expression_matches.next() {
-        for cap in item.captures {
-            return Some(cap.node);
+    /// names of `required_list` required from `react` and called otherwise in the document,
+    /// e.g. `useState` by `React.useState`, and the imports still missing, which are from
+    /// the react module already imported
+    pub fn resolve(
+        &self,
+        required_list: &[RequiredImport],
+    ) -> (Vec<(String, String)>, Vec<RequiredImport>) {
+        let mut rename_list = vec![];
+        let mut missing_list = vec![];
+        for required in required_list {
+            if required.from != "react" {
+                missing_list.push(required.clone());
+                continue;
+            }
+            match self.hook_call(&required.name) {
+                Some(call) if call == required.name => {}
+                Some(call) => rename_list.push((required.name.clone(), call)),
+                None => missing_list.push(RequiredImport {
+                    name: required.name.clone(),
+                    from: self.module.clone().unwrap_or_else(|| "react".to_string()),
+                }),
+            }
         }
+        (rename_list, missing_list)
     }
-    None
 }
@@ -0,0 +1,63 @@
+use ropey::Rope;
+use tower_lsp::lsp_types::{Diagnostic, DiagnosticSeverity};
+use tree_sitter::{Node, Tree};
+
+use crate::position::PositionEncoding;
+
+/// longer text of an `ERROR` node is not quoted in the message
+const MAX_UNEXPECTED_TEXT_LEN: usize = 24;
+
+/// syntax errors recovered by tree-sitter, `ERROR` nodes and zero width `MISSING` nodes
+pub fn get_syntax_diagnostic_list(
+    rope: &Rope,
+    tree: &Tree,
+    encoding: PositionEncoding,
+) -> Vec<Diagnostic> {
+    let mut diagnostic_list = vec![];
+    let root = tree.root_node();
+    if root.has_error() {
+        collect_syntax_error(rope, root, encoding, &mut diagnostic_list);
+    }
+    diagnostic_list
+}
+
+fn collect_syntax_error(
+    rope: &Rope,
+    node: Node,
+    encoding: PositionEncoding,
+    diagnostic_list: &mut Vec<Diagnostic>,
+) {
+    let message = if node.is_missing() {
+        Some(format!("missing `{}`", node.kind()))
+    } else if node.is_error() {
+        let text = rope
+            .byte_slice(node.byte_range())
+            .to_string()
+            .trim()
+            .to_string();
+        if text.is_empty() || text.contains('\n') || text.len() > MAX_UNEXPECTED_TEXT_LEN {
+            Some("unexpected token".to_string())
+        } else {
+            Some(format!("unexpected `{}`", text))
+        }
+    } else {
+        None
+    };
+    if let Some(message) = message {
+        diagnostic_list.push(Diagnostic {
+            range: encoding.node_range(rope, node),
+            severity: Some(DiagnosticSeverity::ERROR),
+            source: Some("tjs".to_string()),
+            message,
+            ..Default::default()
+        });
+        // nested errors are part of the reported one
+        return;
+    }
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        if child.has_error() {
+            collect_syntax_error(rope, child, encoding, diagnostic_list);
+        }
+    }
+}
@@ -0,0 +1,287 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+use log::{debug, error, warn};
+use lsp_text_document::FullTextDocument;
+use ropey::Rope;
+use tokio::sync::{watch, Notify};
+use tower_lsp::{
+    lsp_types::{TextDocumentContentChangeEvent, Url},
+    Client,
+};
+use tree_sitter::Tree;
+
+use crate::{
+    diagnostic::get_syntax_diagnostic_list, helper::get_tree_sitter_edit_from_change,
+    language::LanguageKind, position::PositionEncoding,
+};
+
+/// changes arrived in this window are parsed together
+const PARSE_DEBOUNCE: Duration = Duration::from_millis(50);
+
+/// Everything known about an opened document. The text is updated synchronously in
+/// `did_change`, the tree is parsed by the parse worker of the document and published
+/// as `DocumentSnapshot`
+pub struct DocumentState {
+    pub(crate) document: FullTextDocument,
+    /// the version sent by client, changes of an older version are dropped
+    pub(crate) version: i32,
+    /// bumped on every applied change
+    pub(crate) revision: u64,
+    pub(crate) language: LanguageKind,
+    /// the latest parsed tree with every later edit applied, used as the old tree of the
+    /// next incremental parse, `None` before the first parse or after the whole document is
+    /// replaced
+    pub(crate) edited_tree: Option<Tree>,
+    /// a change was out of the text, which no longer matches the client, incremental changes
+    /// are ignored until the client sends the whole document again
+    pub(crate) out_of_sync: bool,
+    pub(crate) parse_notify: Arc<Notify>,
+    /// `None` until the first parse finishes
+    pub(crate) snapshot_sender: watch::Sender<Option<Arc<DocumentSnapshot>>>,
+}
+
+/// An immutable text and the tree parsed from it, request handlers only read snapshots
+pub struct DocumentSnapshot {
+    pub(crate) version: i32,
+    pub(crate) revision: u64,
+    pub(crate) language: LanguageKind,
+    pub(crate) rope: Rope,
+    /// the text of `rope`, copied once per parse instead of once per request
+    pub(crate) source: String,
+    pub(crate) tree: Tree,
+}
+
+impl DocumentState {
+    /// the first snapshot is parsed by the parse worker
+    pub(crate) fn new(document: FullTextDocument, version: i32, language: LanguageKind) -> Self {
+        let (snapshot_sender, _) = watch::channel(None);
+        Self {
+            document,
+            version,
+            revision: 0,
+            language,
+            edited_tree: None,
+            out_of_sync: false,
+            parse_notify: Arc::new(Notify::new()),
+            snapshot_sender,
+        }
+    }
+
+    /// apply the changes of `didChange` of `version`, returns `false` if the document is out
+    /// of sync and waits for the whole text, changes of a stale version are dropped
+    pub(crate) fn apply_change(
+        &mut self,
+        uri: &Url,
+        version: i32,
+        change_list: Vec<TextDocumentContentChangeEvent>,
+        encoding: PositionEncoding,
+    ) -> bool {
+        if version <= self.version {
+            warn!(
+                "{} version {} is received after version {}, the change is dropped",
+                uri, version, self.version
+            );
+            return !self.out_of_sync;
+        }
+        self.version = version;
+        let start = Instant::now();
+        for change in change_list {
+            let range = match change.range {
+                Some(range) => range,
+                None => {
+                    // the whole document is replaced, the next parse starts from scratch
+                    self.document.update(vec![change], version as i64);
+                    self.edited_tree = None;
+                    self.out_of_sync = false;
+                    continue;
+                }
+            };
+            if self.out_of_sync {
+                continue;
+            }
+            // each range is relative to the text after the previous change applied
+            let range = match encoding.range_to_char_range(&self.document.rope, range) {
+                Some(range) => range,
+                None => {
+                    error!(
+                        "{:?} is out of {} version {}, waiting for the whole document",
+                        range, uri, version
+                    );
+                    self.out_of_sync = true;
+                    self.edited_tree = None;
+                    continue;
+                }
+            };
+            let change = TextDocumentContentChangeEvent {
+                range: Some(range),
+                // deprecated and counted in the unit of client, nothing relies on it
+                range_length: None,
+                text: change.text,
+            };
+            let edit =
+                get_tree_sitter_edit_from_change(&change, &mut self.document, version as i64);
+            if let (Some(tree), Some(edit)) = (self.edited_tree.as_mut(), edit) {
+                tree.edit(&edit);
+            }
+        }
+        debug!("incremental updating: {:?}", start.elapsed());
+        if self.out_of_sync {
+            return false;
+        }
+        self.revision += 1;
+        self.parse_notify.notify_one();
+        true
+    }
+}
+
+/// parse from the chunks of rope, without copying the whole text into a `String`
+pub(crate) fn parse_rope(
+    language: LanguageKind,
+    rope: &Rope,
+    old_tree: Option<&Tree>,
+) -> Option<Tree> {
+    let len = rope.len_bytes();
+    language.parse_with(
+        &mut |byte_index, _| {
+            if byte_index >= len {
+                return &[] as &[u8];
+            }
+            let (chunk, chunk_byte_index, _, _) = rope.chunk_at_byte(byte_index);
+            &chunk.as_bytes()[byte_index - chunk_byte_index..]
+        },
+        old_tree,
+    )
+}
+
+/// Reparse the document in background whenever `parse_notify` is notified and publish the
+/// syntax errors of every snapshot, the worker exits once the document is closed or reopened
+/// with another state
+pub(crate) fn spawn_parse_worker(
+    document_map: Arc<DashMap<Url, DocumentState>>,
+    uri: Url,
+    notify: Arc<Notify>,
+    client: Client,
+    encoding: PositionEncoding,
+) {
+    tokio::spawn(async move {
+        // the first iteration parses the text of `did_open`
+        loop {
+            let (language, version, revision, rope, old_tree) = match document_map.get(&uri) {
+                Some(state) if Arc::ptr_eq(&state.parse_notify, &notify) => (
+                    state.language,
+                    state.version,
+                    state.revision,
+                    state.document.rope.clone(),
+                    state.edited_tree.clone(),
+                ),
+                Some(_) => return,
+                None => break,
+            };
+            let start = Instant::now();
+            let parsed = tokio::task::spawn_blocking(move || {
+                parse_rope(language, &rope, old_tree.as_ref()).map(|tree| {
+                    let source = rope.to_string();
+                    (rope, source, tree)
+                })
+            })
+            .await;
+            match parsed {
+                Ok(Some((rope, source, tree))) => {
+                    debug!("parse {} version {}: {:?}", uri, version, start.elapsed());
+                    let snapshot = match document_map.get_mut(&uri) {
+                        Some(mut state) if Arc::ptr_eq(&state.parse_notify, &notify) => {
+                            // newer edits are already applied to `edited_tree`, keep it in
+                            // that case
+                            if state.revision == revision {
+                                state.edited_tree = Some(tree.clone());
+                            }
+                            let snapshot = Arc::new(DocumentSnapshot {
+                                version,
+                                revision,
+                                language,
+                                rope,
+                                source,
+                                tree,
+                            });
+                            state.snapshot_sender.send_replace(Some(snapshot.clone()));
+                            snapshot
+                        }
+                        Some(_) => return,
+                        None => break,
+                    };
+                    let diagnostic_list =
+                        get_syntax_diagnostic_list(&snapshot.rope, &snapshot.tree, encoding);
+                    client
+                        .publish_diagnostics(uri.clone(), diagnostic_list, Some(snapshot.version))
+                        .await;
+                }
+                // the previous snapshot is kept, the next change parses again
+                _ => warn!("failed to parse {} version {}", uri, version),
+            }
+            notify.notified().await;
+            tokio::time::sleep(PARSE_DEBOUNCE).await;
+        }
+        // `did_close` clears the diagnostics too, but the last publish above may arrive later
+        client.publish_diagnostics(uri, vec![], None).await;
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tower_lsp::lsp_types::{Position, Range};
+
+    fn state(text: &str) -> DocumentState {
+        let uri = Url::parse("file:///workspace/a.ts").unwrap();
+        DocumentState::new(
+            FullTextDocument::new(uri, "typescript".into(), 1, text.into()),
+            1,
+            LanguageKind::TypeScript,
+        )
+    }
+
+    fn change(
+        range: Option<((u32, u32), (u32, u32))>,
+        text: &str,
+    ) -> TextDocumentContentChangeEvent {
+        TextDocumentContentChangeEvent {
+            range: range.map(|(start, end)| {
+                Range::new(Position::new(start.0, start.1), Position::new(end.0, end.1))
+            }),
+            range_length: None,
+            text: text.into(),
+        }
+    }
+
+    #[test]
+    fn stale_version_is_dropped() {
+        let uri = Url::parse("file:///workspace/a.ts").unwrap();
+        let mut state = state("let a = 1;");
+        let encoding = PositionEncoding::default();
+        assert!(state.apply_change(&uri, 3, vec![change(Some(((0, 4), (0, 5))), "b")], encoding));
+        assert!(state.apply_change(&uri, 2, vec![change(Some(((0, 4), (0, 5))), "c")], encoding));
+        assert_eq!(state.document.rope.to_string(), "let b = 1;");
+        assert_eq!((state.version, state.revision), (3, 1));
+    }
+
+    #[test]
+    fn out_of_range_waits_for_whole_document() {
+        let uri = Url::parse("file:///workspace/a.ts").unwrap();
+        let mut state = state("let a = 1;");
+        let encoding = PositionEncoding::default();
+        assert!(!state.apply_change(&uri, 2, vec![change(Some(((3, 0), (3, 1))), "b")], encoding));
+        assert!(state.out_of_sync);
+        assert_eq!(state.revision, 0);
+        // incremental changes can't be applied to a text that doesn't match the client
+        assert!(!state.apply_change(&uri, 3, vec![change(Some(((0, 4), (0, 5))), "c")], encoding));
+        assert_eq!(state.document.rope.to_string(), "let a = 1;");
+        assert!(state.apply_change(&uri, 4, vec![change(None, "let d = 1;")], encoding));
+        assert!(!state.out_of_sync);
+        assert_eq!(state.document.rope.to_string(), "let d = 1;");
+        assert_eq!((state.version, state.revision), (4, 1));
+    }
+}
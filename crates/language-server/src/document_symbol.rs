@@ -1,9 +1,203 @@
+use std::{ops::Range, time::Instant};
+
+use log::debug;
 use lsp_text_document::lsp_types::{
-    DocumentSymbolParams, DocumentSymbolResponse, Location, Position, Range, SymbolInformation,
-    SymbolKind,
+    DocumentSymbol, DocumentSymbolParams, DocumentSymbolResponse, SymbolKind,
 };
 
-use tower_lsp::jsonrpc;
-use tree_sitter::{Query, QueryCursor};
+use streaming_iterator::StreamingIterator;
+use tree_sitter::{Node, Query, QueryCursor};
+
+use crate::{document::DocumentSnapshot, query_registry::QueryName, Backend};
+
+/// A flat symbol with the byte range of the node it covers, used to restore the nesting,
+/// lsp ranges of the symbol are filled from the byte ranges at last
+struct SymbolNode {
+    symbol: DocumentSymbol,
+    start_byte: usize,
+    end_byte: usize,
+    name_byte_range: Range<usize>,
+}
+
+pub async fn get_document_symbol(
+    back_end: &Backend,
+    params: DocumentSymbolParams,
+) -> Option<DocumentSymbolResponse> {
+    let snapshot = back_end.get_snapshot(&params.text_document.uri).await?;
+    let DocumentSnapshot {
+        rope,
+        source,
+        language,
+        tree,
+        ..
+    } = &*snapshot;
+    let duration = Instant::now();
+    let root = tree.root_node();
+
+    let mut symbol_list = vec![];
+    for i in 0..root.named_child_count() {
+        let mut declaration = root.named_child(i).unwrap();
+        if declaration.kind() == "export_statement" {
+            match declaration.child_by_field_name("declaration") {
+                Some(inner) => declaration = inner,
+                None => continue,
+            }
+        }
+        collect_declaration_symbol(declaration, source, &mut symbol_list);
+    }
+
+    // plain typescript grammar has no jsx node, so there is no such query for it
+    if let Some(query) = back_end
+        .query_registry
+        .get(*language, QueryName::DocumentSymbol)
+    {
+        collect_component_symbol(query, root, source, &mut symbol_list);
+    }
+
+    let encoding = back_end.position_encoding();
+    for symbol_node in symbol_list.iter_mut() {
+        symbol_node.symbol.range =
+            encoding.byte_range_to_range(rope, symbol_node.start_byte, symbol_node.end_byte);
+        symbol_node.symbol.selection_range = encoding.byte_range_to_range(
+            rope,
+            symbol_node.name_byte_range.start,
+            symbol_node.name_byte_range.end,
+        );
+    }
+    symbol_list.sort_by(|a, b| {
+        a.start_byte
+            .cmp(&b.start_byte)
+            .then(b.end_byte.cmp(&a.end_byte))
+    });
+    let result = nest_symbol_list(symbol_list);
+    debug!("document-symbol: {:?}", duration.elapsed());
+    Some(DocumentSymbolResponse::Nested(result))
+}
+
+fn collect_component_symbol(
+    query: &Query,
+    root: Node,
+    source: &str,
+    symbol_list: &mut Vec<SymbolNode>,
+) {
+    let mut cursor = QueryCursor::new();
+    let mut matches = cursor.matches(query, root, source.as_bytes());
+    while let Some(item) = matches.next() {
+        for cap in item.captures {
+            let name = cap.node;
+            // `jsx_opening_element` lives in a `jsx_element`, which is the real extent of the component
+            let element = match name.parent() {
+                Some(opening) if opening.kind() == "jsx_opening_element" => opening.parent(),
+                parent => parent,
+            };
+            if let (Some(element), Ok(text)) = (element, name.utf8_text(source.as_bytes())) {
+                symbol_list.push(create_symbol_node(
+                    text.to_string(),
+                    SymbolKind::CLASS,
+                    element,
+                    name,
+                ));
+            }
+        }
+    }
+}
+
+/// top level function like or class declaration
+fn collect_declaration_symbol(declaration: Node, source: &str, symbol_list: &mut Vec<SymbolNode>) {
+    match declaration.kind() {
+        "function_declaration" | "generator_function_declaration" => {
+            if let Some(name) = declaration.child_by_field_name("name") {
+                symbol_list.push(create_symbol_node(
+                    source[name.byte_range()].to_string(),
+                    SymbolKind::FUNCTION,
+                    declaration,
+                    name,
+                ));
+            }
+        }
+        "class_declaration" | "abstract_class_declaration" => {
+            if let Some(name) = declaration.child_by_field_name("name") {
+                symbol_list.push(create_symbol_node(
+                    source[name.byte_range()].to_string(),
+                    SymbolKind::CLASS,
+                    declaration,
+                    name,
+                ));
+            }
+        }
+        "lexical_declaration" | "variable_declaration" => {
+            for i in 0..declaration.named_child_count() {
+                let declarator = declaration.named_child(i).unwrap();
+                let (name, value) = match (
+                    declarator.child_by_field_name("name"),
+                    declarator.child_by_field_name("value"),
+                ) {
+                    (Some(name), Some(value)) if name.kind() == "identifier" => (name, value),
+                    _ => continue,
+                };
+                let kind = match value.kind() {
+                    "arrow_function" | "function_expression" | "generator_function" => {
+                        SymbolKind::FUNCTION
+                    }
+                    "class" => SymbolKind::CLASS,
+                    _ => continue,
+                };
+                symbol_list.push(create_symbol_node(
+                    source[name.byte_range()].to_string(),
+                    kind,
+                    declarator,
+                    name,
+                ));
+            }
+        }
+        _ => {}
+    }
+}
+
+fn create_symbol_node(name: String, kind: SymbolKind, node: Node, name_node: Node) -> SymbolNode {
+    #[allow(deprecated)]
+    let symbol = DocumentSymbol {
+        name,
+        detail: None,
+        kind,
+        tags: None,
+        deprecated: None,
+        range: Default::default(),
+        selection_range: Default::default(),
+        children: None,
+    };
+    SymbolNode {
+        symbol,
+        start_byte: node.start_byte(),
+        end_byte: node.end_byte(),
+        name_byte_range: name_node.byte_range(),
+    }
+}
 
-use crate::{query_pattern::DOCUMENT_SYMBOL_QUERY_PATTERN, Backend};
+/// `symbol_list` must be sorted by start asc and end desc, a symbol becomes a child of
+/// the nearest previous symbol containing it
+fn nest_symbol_list(symbol_list: Vec<SymbolNode>) -> Vec<DocumentSymbol> {
+    let mut result = vec![];
+    let mut stack: Vec<SymbolNode> = vec![];
+    let pop = |stack: &mut Vec<SymbolNode>, result: &mut Vec<DocumentSymbol>| {
+        let top = stack.pop().unwrap();
+        match stack.last_mut() {
+            Some(parent) => parent
+                .symbol
+                .children
+                .get_or_insert_with(Vec::new)
+                .push(top.symbol),
+            None => result.push(top.symbol),
+        }
+    };
+    for symbol in symbol_list {
+        while matches!(stack.last(), Some(top) if top.end_byte <= symbol.start_byte) {
+            pop(&mut stack, &mut result);
+        }
+        stack.push(symbol);
+    }
+    while !stack.is_empty() {
+        pop(&mut stack, &mut result);
+    }
+    result
+}
@@ -1,19 +1,18 @@
 use log::debug;
 use lsp_text_document::FullTextDocument;
 use tower_lsp::lsp_types::*;
-use tree_sitter::{InputEdit, Node, Point};
+use tree_sitter::{InputEdit, Node};
 
+use crate::position::byte_to_point;
+
+/// `change.range` must be counted in chars, see `PositionEncoding::range_to_char_range`
 pub fn get_tree_sitter_edit_from_change(
     change: &TextDocumentContentChangeEvent,
     document: &mut FullTextDocument,
     version: i64,
 ) -> Option<InputEdit> {
-    if change.range.is_none() || change.range_length.is_none() {
-        return None;
-    }
-
     // this is utf8 based bytes index
-    let range = change.range.unwrap();
+    let range = change.range?;
     let start = range.start;
     let end = range.end;
     let start_char = document.rope.line_to_char(start.line as usize) + start.character as usize;
@@ -21,6 +20,8 @@ pub fn get_tree_sitter_edit_from_change(
 
     let start_byte = document.rope.char_to_byte(start_char);
     let old_end_byte = document.rope.char_to_byte(old_end_char);
+    let start_position = byte_to_point(&document.rope, start_byte);
+    let old_end_position = byte_to_point(&document.rope, old_end_byte);
     // let start_byte = document.rope.line
     // let start_byte = position_to_byte_index(
     //     &files,
@@ -37,17 +38,13 @@ pub fn get_tree_sitter_edit_from_change(
     document.update(vec![change.clone()], version);
     let new_end_char = start_char + change.text.chars().count();
     let new_end_byte = document.rope.char_to_byte(new_end_char);
-
-    let new_end_line = document.rope.char_to_line(new_end_char);
-    let new_end_line_first_character = document.rope.line_to_char(new_end_line);
-    let new_end_character = new_end_byte - new_end_line_first_character;
     Some(InputEdit {
         start_byte,
         old_end_byte,
         new_end_byte,
-        start_position: Point::new(start.line as usize, start.character as usize),
-        old_end_position: Point::new(end.line as usize, end.character as usize),
-        new_end_position: Point::new(new_end_line, new_end_character),
+        start_position,
+        old_end_position,
+        new_end_position: byte_to_point(&document.rope, new_end_byte),
     })
 }
 
@@ -73,15 +70,3 @@ pub fn pretty_print(source_code: &str, root: Node, level: usize) {
         pretty_print(source_code, node, level + 1);
     }
 }
-
-pub fn generate_lsp_range(
-    start_row: u32,
-    start_column: u32,
-    end_row: u32,
-    end_column: u32,
-) -> Range {
-    Range::new(
-        Position::new(start_row, start_column),
-        Position::new(end_row, end_column),
-    )
-}
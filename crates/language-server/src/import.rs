@@ -0,0 +1,411 @@
+use ropey::Rope;
+use streaming_iterator::StreamingIterator;
+use tower_lsp::lsp_types::{Position, Range, TextEdit};
+use tree_sitter::{Node, Query, QueryCursor};
+
+use crate::position::PositionEncoding;
+use crate::template::RequiredImport;
+
+/// An import statement a missing name could be merged into
+#[derive(Debug, Clone)]
+struct ImportStatement {
+    module: String,
+    /// imported names of `{ ... }`, not the aliases
+    named_list: Vec<String>,
+    /// after the last specifier of `{ ... }`, or after the `{` if it is empty
+    named_insert: Option<(Position, bool)>,
+    /// after the default import of `import React from 'react'` without `{ ... }`
+    default_end: Option<Position>,
+}
+
+/// Imports of a document, to add the names a template requires
+#[derive(Debug, Clone)]
+pub struct DocumentImport {
+    statement_list: Vec<ImportStatement>,
+    /// where a new import statement goes, after the last import, the directives or at the start
+    new_statement_position: Position,
+    /// the document ends with the last import, a new statement needs a newline before it
+    at_document_end: bool,
+    /// quote and semicolon of the last import or directive
+    quote: char,
+    semicolon: bool,
+}
+
+impl Default for DocumentImport {
+    fn default() -> Self {
+        Self {
+            statement_list: vec![],
+            new_statement_position: Position::new(0, 0),
+            at_document_end: false,
+            quote: '\'',
+            semicolon: false,
+        }
+    }
+}
+
+impl DocumentImport {
+    pub fn new(
+        import_query: Option<&Query>,
+        rope: &Rope,
+        encoding: PositionEncoding,
+        source: &str,
+        root: Node,
+    ) -> Self {
+        let mut document_import = DocumentImport::default();
+        let query = match import_query {
+            Some(query) => query,
+            None => return document_import,
+        };
+        let bytes = source.as_bytes();
+        let text = |node: Node| node.utf8_text(bytes).unwrap_or_default().to_string();
+        let mut last_statement = None;
+        let mut cursor = QueryCursor::new();
+        let mut matches = cursor.matches(query, root, bytes);
+        while let Some(item) = matches.next() {
+            let (mut statement, mut module) = (None, None);
+            for capture in item.captures {
+                match query.capture_names()[capture.index as usize] {
+                    "statement" => statement = Some(capture.node),
+                    "source" => module = Some(text(capture.node)),
+                    _ => {}
+                }
+            }
+            let (statement, module) = match (statement, module) {
+                (Some(statement), Some(module)) => (statement, module),
+                _ => continue,
+            };
+            // only imports at the top level count for the position of a new one
+            if statement
+                .parent()
+                .is_some_and(|parent| parent.kind() == "program")
+            {
+                last_statement = Some(statement);
+            }
+            let mut statement_cursor = statement.walk();
+            let mut clause = None;
+            for child in statement.children(&mut statement_cursor) {
+                match child.kind() {
+                    // a value can't be merged into `import type { ... }`
+                    "type" | "typeof" => {
+                        clause = None;
+                        break;
+                    }
+                    "import_clause" => clause = Some(child),
+                    _ => {}
+                }
+            }
+            let clause = match clause {
+                Some(clause) => clause,
+                None => continue,
+            };
+            let mut import_statement = ImportStatement {
+                module,
+                named_list: vec![],
+                named_insert: None,
+                default_end: None,
+            };
+            let mut has_namespace = false;
+            let mut clause_cursor = clause.walk();
+            for child in clause.named_children(&mut clause_cursor) {
+                match child.kind() {
+                    "identifier" => {
+                        import_statement.default_end =
+                            Some(encoding.byte_to_position(rope, child.end_byte()))
+                    }
+                    "namespace_import" => has_namespace = true,
+                    "named_imports" => {
+                        let mut specifier_cursor = child.walk();
+                        let specifier_list = child
+                            .named_children(&mut specifier_cursor)
+                            .filter(|specifier| specifier.kind() == "import_specifier")
+                            .collect::<Vec<_>>();
+                        import_statement.named_list = specifier_list
+                            .iter()
+                            .filter_map(|specifier| specifier.child_by_field_name("name"))
+                            .map(text)
+                            .collect();
+                        import_statement.named_insert = Some(match specifier_list.last() {
+                            Some(last) => (encoding.byte_to_position(rope, last.end_byte()), false),
+                            None => (
+                                encoding.byte_to_position(rope, child.start_byte() + 1),
+                                true,
+                            ),
+                        });
+                    }
+                    _ => {}
+                }
+            }
+            // `import * as React, { useState }` is not valid
+            if has_namespace || import_statement.named_insert.is_some() {
+                import_statement.default_end = None;
+            }
+            document_import.statement_list.push(import_statement);
+        }
+        // without imports, a new one goes after the `'use client'` directive prologue
+        let last_statement = last_statement.or_else(|| last_directive(root));
+        if let Some(statement) = last_statement {
+            let statement_text = text(statement);
+            document_import.semicolon = statement_text.trim_end().ends_with(';');
+            let quoted = statement
+                .child_by_field_name("source")
+                .or_else(|| statement.named_child(0));
+            if let Some(quoted) = quoted {
+                document_import.quote = text(quoted).chars().next().unwrap_or('\'');
+            }
+            let end_row = statement.end_position().row;
+            if end_row + 1 < rope.len_lines() {
+                document_import.new_statement_position = Position::new(end_row as u32 + 1, 0);
+            } else {
+                document_import.new_statement_position =
+                    encoding.byte_to_position(rope, statement.end_byte());
+                document_import.at_document_end = true;
+            }
+        }
+        document_import
+    }
+
+    /// edits importing the names not imported yet, names of the same module go into one
+    /// statement
+    pub fn edit_list(&self, required_import_list: &[RequiredImport]) -> Vec<TextEdit> {
+        let mut module_list: Vec<(&str, Vec<&str>)> = vec![];
+        for required in required_import_list {
+            let imported = self.statement_list.iter().any(|statement| {
+                statement.module == required.from && statement.named_list.contains(&required.name)
+            });
+            if imported {
+                continue;
+            }
+            match module_list
+                .iter_mut()
+                .find(|(module, _)| *module == required.from)
+            {
+                Some((_, name_list)) if !name_list.contains(&required.name.as_str()) => {
+                    name_list.push(&required.name)
+                }
+                Some(_) => {}
+                None => module_list.push((&required.from, vec![&required.name])),
+            }
+        }
+        let mut new_statement_list = vec![];
+        let mut edit_list = vec![];
+        for (module, name_list) in module_list {
+            let name_list = name_list.join(", ");
+            let statement_list = self
+                .statement_list
+                .iter()
+                .filter(|statement| statement.module == module);
+            let mut merged = None;
+            for statement in statement_list {
+                merged = match (statement.named_insert, statement.default_end) {
+                    (Some((position, true)), _) => Some(TextEdit::new(
+                        Range::new(position, position),
+                        format!(" {} ", name_list),
+                    )),
+                    (Some((position, false)), _) => Some(TextEdit::new(
+                        Range::new(position, position),
+                        format!(", {}", name_list),
+                    )),
+                    (None, Some(position)) => Some(TextEdit::new(
+                        Range::new(position, position),
+                        format!(", {{ {} }}", name_list),
+                    )),
+                    _ => continue,
+                };
+                break;
+            }
+            match merged {
+                Some(edit) => edit_list.push(edit),
+                None => new_statement_list.push(format!(
+                    "import {{ {} }} from {}{}{}{}",
+                    name_list,
+                    self.quote,
+                    module,
+                    self.quote,
+                    if self.semicolon { ";" } else { "" }
+                )),
+            }
+        }
+        if !new_statement_list.is_empty() {
+            let position = self.new_statement_position;
+            let new_text = if self.at_document_end {
+                format!("\n{}", new_statement_list.join("\n"))
+            } else {
+                format!("{}\n", new_statement_list.join("\n"))
+            };
+            edit_list.push(TextEdit::new(Range::new(position, position), new_text));
+        }
+        edit_list
+    }
+}
+
+/// the last directive like `'use strict'` at the start of the program
+fn last_directive(root: Node) -> Option<Node> {
+    let mut cursor = root.walk();
+    let mut last = None;
+    for child in root.named_children(&mut cursor) {
+        match child.kind() {
+            "comment" => {}
+            "expression_statement"
+                if child
+                    .named_child(0)
+                    .is_some_and(|expression| expression.kind() == "string") =>
+            {
+                last = Some(child)
+            }
+            _ => break,
+        }
+    }
+    last
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::language::LanguageKind;
+    use crate::query_registry::{QueryName, QueryRegistry};
+
+    /// `code` after the edits importing `required_list` of `(name, from)`
+    fn import(code: &str, required_list: &[(&str, &str)]) -> String {
+        let language = LanguageKind::TypeScriptReact;
+        let tree = language
+            .parse_with(&mut |byte_index, _| &code.as_bytes()[byte_index..], None)
+            .unwrap();
+        let rope = Rope::from_str(code);
+        let encoding = PositionEncoding::default();
+        let query_registry = QueryRegistry::new();
+        let document_import = DocumentImport::new(
+            query_registry.get(language, QueryName::ImportStatement),
+            &rope,
+            encoding,
+            code,
+            tree.root_node(),
+        );
+        let required_list = required_list
+            .iter()
+            .map(|(name, from)| RequiredImport {
+                name: name.to_string(),
+                from: from.to_string(),
+            })
+            .collect::<Vec<_>>();
+        let mut edit_list = document_import.edit_list(&required_list);
+        edit_list.sort_by_key(|edit| edit.range.start);
+        let mut rope = rope;
+        for edit in edit_list.iter().rev() {
+            let start = encoding.position_to_char(&rope, edit.range.start).unwrap();
+            let end = encoding.position_to_char(&rope, edit.range.end).unwrap();
+            rope.remove(start..end);
+            rope.insert(start, &edit.new_text);
+        }
+        rope.to_string()
+    }
+
+    #[test]
+    fn merge_into_named_import() {
+        assert_eq!(
+            import("import { a } from 'x'\nfoo", &[("b", "x")]),
+            "import { a, b } from 'x'\nfoo"
+        );
+        assert_eq!(
+            import("import {} from 'x'\nfoo", &[("b", "x"), ("c", "x")]),
+            "import { b, c } from 'x'\nfoo"
+        );
+        // aliased names are imported by their original names
+        assert_eq!(
+            import("import { a as b } from 'x'\nfoo", &[("a", "x"), ("b", "x")]),
+            "import { a as b, b } from 'x'\nfoo"
+        );
+        assert_eq!(
+            import("import { a } from 'x'\n", &[("a", "x")]),
+            "import { a } from 'x'\n"
+        );
+    }
+
+    #[test]
+    fn merge_into_default_import() {
+        assert_eq!(
+            import(
+                "import React, { useState } from 'react';\n",
+                &[("useEffect", "react")]
+            ),
+            "import React, { useState, useEffect } from 'react';\n"
+        );
+        assert_eq!(
+            import("import React from 'react';\n", &[("useEffect", "react")]),
+            "import React, { useEffect } from 'react';\n"
+        );
+        // a named import can't follow a namespace one
+        assert_eq!(
+            import(
+                "import * as React from 'react';\n",
+                &[("useEffect", "react")]
+            ),
+            "import * as React from 'react';\nimport { useEffect } from 'react';\n"
+        );
+    }
+
+    #[test]
+    fn type_only_import_is_not_merged() {
+        assert_eq!(
+            import("import type { A } from 'x'\nfoo", &[("b", "x")]),
+            "import type { A } from 'x'\nimport { b } from 'x'\nfoo"
+        );
+        assert_eq!(
+            import("import { type A } from 'x'\nfoo", &[("b", "x")]),
+            "import { type A, b } from 'x'\nfoo"
+        );
+    }
+
+    #[test]
+    fn new_import_after_last_import() {
+        assert_eq!(
+            import(
+                "import a from \"a\";\nimport b from \"b\";\n\nfoo();\n",
+                &[("c", "c"), ("d", "c"), ("e", "e")]
+            ),
+            "import a from \"a\";\nimport b from \"b\";\nimport { c, d } from \"c\";\nimport { e } from \"e\";\n\nfoo();\n"
+        );
+        // the last import ends the document
+        assert_eq!(
+            import("import a from 'a'", &[("c", "c")]),
+            "import a from 'a'\nimport { c } from 'c'"
+        );
+        // imports inside a module declaration don't count
+        assert_eq!(
+            import(
+                "foo();\ndeclare module 'm' {\n  import a from 'a'\n}\n",
+                &[("c", "c")]
+            ),
+            "import { c } from 'c'\nfoo();\ndeclare module 'm' {\n  import a from 'a'\n}\n"
+        );
+    }
+
+    #[test]
+    fn new_import_in_document_without_import() {
+        assert_eq!(import("", &[("c", "c")]), "import { c } from 'c'\n");
+        assert_eq!(
+            import("foo();\n", &[("c", "c")]),
+            "import { c } from 'c'\nfoo();\n"
+        );
+    }
+
+    #[test]
+    fn new_import_after_directive() {
+        assert_eq!(
+            import("'use client';\nfoo();\n", &[("c", "c")]),
+            "'use client';\nimport { c } from 'c';\nfoo();\n"
+        );
+        assert_eq!(
+            import("// a\n\"use strict\"\n'use client'\n", &[("c", "c")]),
+            "// a\n\"use strict\"\n'use client'\nimport { c } from 'c'\n"
+        );
+        assert_eq!(
+            import("\"use client\"", &[("c", "c")]),
+            "\"use client\"\nimport { c } from \"c\""
+        );
+        // a string after a statement is not a directive
+        assert_eq!(
+            import("foo();\n'bar';\n", &[("c", "c")]),
+            "import { c } from 'c'\nfoo();\n'bar';\n"
+        );
+    }
+}
@@ -0,0 +1,246 @@
+use std::path::Path;
+
+use regex::Regex;
+
+/// How the body of a snippet is indented, from `editor.insertSpaces` and `editor.tabSize`,
+/// overridden by `.editorconfig`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IndentStyle {
+    pub insert_spaces: bool,
+    pub tab_size: usize,
+}
+
+impl Default for IndentStyle {
+    fn default() -> Self {
+        Self {
+            insert_spaces: true,
+            tab_size: 4,
+        }
+    }
+}
+
+impl IndentStyle {
+    /// a level of indentation
+    pub fn unit(&self) -> String {
+        if self.insert_spaces {
+            " ".repeat(self.tab_size)
+        } else {
+            "\t".to_string()
+        }
+    }
+
+    /// apply `indent_style`, `indent_size` and `tab_width` of the `.editorconfig` files from
+    /// the directory of `file_path` up to the one with `root = true`, the closer one wins
+    pub fn with_editorconfig(self, file_path: &Path) -> Self {
+        let mut property_list = vec![];
+        let mut dir = file_path.parent();
+        while let Some(current) = dir {
+            if let Ok(text) = std::fs::read_to_string(current.join(".editorconfig")) {
+                let relative_path = file_path
+                    .strip_prefix(current)
+                    .map(|path| path.to_string_lossy().replace('\\', "/"))
+                    .unwrap_or_default();
+                let (is_root, section_property_list) = parse_editorconfig(&text, &relative_path);
+                // farther files come first, so closer ones override them
+                property_list.splice(0..0, section_property_list);
+                if is_root {
+                    break;
+                }
+            }
+            dir = current.parent();
+        }
+        let mut style = self;
+        let mut indent_size = None;
+        let mut tab_width = None;
+        for (key, value) in property_list {
+            match key.as_str() {
+                "indent_style" => match value.as_str() {
+                    "tab" => style.insert_spaces = false,
+                    "space" => style.insert_spaces = true,
+                    _ => {}
+                },
+                "indent_size" => indent_size = Some(value),
+                "tab_width" => tab_width = value.parse::<usize>().ok(),
+                _ => {}
+            }
+        }
+        match indent_size {
+            Some(size) if size == "tab" => style.tab_size = tab_width.unwrap_or(style.tab_size),
+            Some(size) => style.tab_size = size.parse().unwrap_or(style.tab_size),
+            None => style.tab_size = tab_width.unwrap_or(style.tab_size),
+        }
+        style
+    }
+}
+
+/// whether the file is `root = true`, and properties of the sections matching
+/// `relative_path` in order
+fn parse_editorconfig(text: &str, relative_path: &str) -> (bool, Vec<(String, String)>) {
+    let mut is_root = false;
+    let mut property_list = vec![];
+    // properties before any section are only allowed to be `root`
+    let mut section_matched = None;
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if let Some(section) = line
+            .strip_prefix('[')
+            .and_then(|line| line.strip_suffix(']'))
+        {
+            section_matched = Some(
+                editorconfig_glob_to_regex(section)
+                    .is_some_and(|regex| regex.is_match(relative_path)),
+            );
+            continue;
+        }
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) => (key.trim().to_lowercase(), value.trim().to_lowercase()),
+            None => continue,
+        };
+        match section_matched {
+            None if key == "root" => is_root = value == "true",
+            Some(true) => property_list.push((key, value)),
+            _ => {}
+        }
+    }
+    (is_root, property_list)
+}
+
+/// `*` and `?` don't match `/`, `**` matches any path, `{a,b}` matches either, a glob without
+/// `/` matches the file name in any directory
+fn editorconfig_glob_to_regex(glob: &str) -> Option<Regex> {
+    let mut pattern = String::from("^");
+    let glob = match glob.strip_prefix('/') {
+        Some(glob) => glob,
+        None if !glob.contains('/') => {
+            pattern.push_str("(?:.*/)?");
+            glob
+        }
+        None => glob,
+    };
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                pattern.push_str(".*");
+            }
+            '*' => pattern.push_str("[^/]*"),
+            '?' => pattern.push_str("[^/]"),
+            '{' => pattern.push_str("(?:"),
+            '}' => pattern.push(')'),
+            ',' => pattern.push('|'),
+            _ => pattern.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    pattern.push('$');
+    Regex::new(&pattern).ok()
+}
+
+/// the smallest leading spaces of the lines after the first, which is a level of
+/// indentation of `code`
+pub fn space_unit(code: &str) -> usize {
+    code.lines()
+        .skip(1)
+        .map(|line| line.chars().take_while(|c| *c == ' ').count())
+        .filter(|count| *count > 0)
+        .min()
+        .unwrap_or(1)
+}
+
+/// Reindent the lines started in `text`, a leading tab or `space_unit` leading spaces is a
+/// level of `style`, and `base_indent`, the indentation of the line the snippet is inserted
+/// at, is prepended
+pub fn reindent(text: &str, space_unit: usize, base_indent: &str, style: IndentStyle) -> String {
+    let unit = style.unit();
+    let mut reindented = String::with_capacity(text.len());
+    for (i, line) in text.split('\n').enumerate() {
+        if i == 0 {
+            reindented.push_str(line);
+            continue;
+        }
+        reindented.push('\n');
+        let content = line.trim_start_matches([' ', '\t']);
+        let leading = &line[..line.len() - content.len()];
+        let level = leading.chars().filter(|c| *c == '\t').count()
+            + leading.chars().filter(|c| *c == ' ').count() / space_unit;
+        reindented.push_str(base_indent);
+        reindented.push_str(&unit.repeat(level));
+        reindented.push_str(content);
+    }
+    reindented
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn editorconfig_glob() {
+        let table = [
+            ("*", "a.ts", true),
+            ("*", "src/a.ts", true),
+            ("*.ts", "src/deep/a.ts", true),
+            ("*.ts", "a.tsx", false),
+            ("*.{ts,tsx}", "src/a.tsx", true),
+            ("*.{ts,tsx}", "src/a.js", false),
+            ("a?.ts", "ab.ts", true),
+            ("a?.ts", "a/.ts", false),
+            ("src/*.ts", "src/a.ts", true),
+            ("src/*.ts", "src/deep/a.ts", false),
+            ("src/**.ts", "src/deep/a.ts", true),
+            ("src/*.ts", "lib/src/a.ts", false),
+            ("/a.ts", "a.ts", true),
+            ("/a.ts", "src/a.ts", false),
+            ("a.b", "axb", false),
+        ];
+        for (glob, path, expected) in table {
+            let regex = editorconfig_glob_to_regex(glob).unwrap();
+            assert_eq!(regex.is_match(path), expected, "{} {}", glob, path);
+        }
+    }
+
+    #[test]
+    fn editorconfig_section() {
+        let text = "
+root = true
+
+[*]
+indent_style = space
+indent_size = 2
+
+# comment
+[*.{ts,tsx}]
+Indent_Style = Tab
+tab_width = 8
+
+[lib/**]
+indent_size = 3
+";
+        let (is_root, property_list) = parse_editorconfig(text, "src/a.ts");
+        assert!(is_root);
+        assert_eq!(
+            property_list,
+            [
+                ("indent_style".into(), "space".into()),
+                ("indent_size".into(), "2".into()),
+                ("indent_style".into(), "tab".into()),
+                ("tab_width".into(), "8".into()),
+            ]
+        );
+        let (_, property_list) = parse_editorconfig(text, "lib/a.js");
+        assert_eq!(
+            property_list,
+            [
+                ("indent_style".into(), "space".into()),
+                ("indent_size".into(), "2".into()),
+                ("indent_size".into(), "3".into()),
+            ]
+        );
+        // `root` in a section is a property of the section
+        let (is_root, _) = parse_editorconfig("[*]\nroot = true", "a.ts");
+        assert!(!is_root);
+    }
+}
@@ -0,0 +1,146 @@
+use std::{cell::RefCell, collections::HashMap};
+
+use tower_lsp::lsp_types::Url;
+use tree_sitter::{Language, Parser, Point, Tree};
+use tree_sitter_typescript::{LANGUAGE_TSX, LANGUAGE_TYPESCRIPT};
+
+thread_local! {
+    /// `Parser` is not `Sync`, each worker thread keeps its own parser of every grammar,
+    /// so parsing never waits on another document
+    static PARSER_POOL: RefCell<HashMap<Grammar, Parser>> = RefCell::new(HashMap::new());
+}
+
+/// Languages the server handles, plain typescript needs its own grammar since `<T>expr`
+/// is a type assertion rather than a jsx element there, javascript is parsed with the tsx
+/// grammar which is a superset of it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LanguageKind {
+    TypeScript,
+    TypeScriptReact,
+    JavaScript,
+    JavaScriptReact,
+}
+
+impl LanguageKind {
+    pub const ALL: [LanguageKind; 4] = [
+        LanguageKind::TypeScript,
+        LanguageKind::TypeScriptReact,
+        LanguageKind::JavaScript,
+        LanguageKind::JavaScriptReact,
+    ];
+
+    /// prefer the `language_id` sent by client, fallback to the extension of uri, and at last tsx
+    pub fn new(language_id: &str, uri: &Url) -> Self {
+        Self::from_language_id(language_id)
+            .or_else(|| Self::from_uri(uri))
+            .unwrap_or(LanguageKind::TypeScriptReact)
+    }
+
+    pub fn from_language_id(language_id: &str) -> Option<Self> {
+        match language_id {
+            "typescript" => Some(LanguageKind::TypeScript),
+            "typescriptreact" => Some(LanguageKind::TypeScriptReact),
+            "javascript" => Some(LanguageKind::JavaScript),
+            "javascriptreact" => Some(LanguageKind::JavaScriptReact),
+            _ => None,
+        }
+    }
+
+    pub fn from_uri(uri: &Url) -> Option<Self> {
+        let extension = uri.path().rsplit_once('.')?.1;
+        match extension {
+            "ts" | "mts" | "cts" => Some(LanguageKind::TypeScript),
+            "tsx" => Some(LanguageKind::TypeScriptReact),
+            "js" | "mjs" | "cjs" => Some(LanguageKind::JavaScript),
+            "jsx" => Some(LanguageKind::JavaScriptReact),
+            _ => None,
+        }
+    }
+
+    pub fn language_id(&self) -> &'static str {
+        match self {
+            LanguageKind::TypeScript => "typescript",
+            LanguageKind::TypeScriptReact => "typescriptreact",
+            LanguageKind::JavaScript => "javascript",
+            LanguageKind::JavaScriptReact => "javascriptreact",
+        }
+    }
+
+    /// the language of a markdown code block
+    pub fn code_fence(&self) -> &'static str {
+        match self {
+            LanguageKind::TypeScript => "typescript",
+            LanguageKind::TypeScriptReact => "tsx",
+            LanguageKind::JavaScript => "javascript",
+            LanguageKind::JavaScriptReact => "jsx",
+        }
+    }
+
+    pub fn is_typescript(&self) -> bool {
+        matches!(
+            self,
+            LanguageKind::TypeScript | LanguageKind::TypeScriptReact
+        )
+    }
+
+    pub fn grammar(&self) -> Grammar {
+        match self {
+            LanguageKind::TypeScript => Grammar::TypeScript,
+            _ => Grammar::Tsx,
+        }
+    }
+
+    pub fn tree_sitter_language(&self) -> Language {
+        self.grammar().tree_sitter_language()
+    }
+
+    /// parse with the pooled parser of current thread, see `Parser::parse_with`
+    pub fn parse_with<T: AsRef<[u8]>, F: FnMut(usize, Point) -> T>(
+        &self,
+        callback: &mut F,
+        old_tree: Option<&Tree>,
+    ) -> Option<Tree> {
+        let grammar = self.grammar();
+        PARSER_POOL.with(|pool| {
+            pool.borrow_mut()
+                .entry(grammar)
+                .or_insert_with(|| grammar.create_parser())
+                .parse_with(callback, old_tree)
+        })
+    }
+}
+
+/// The tree-sitter grammars behind `LanguageKind`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Grammar {
+    TypeScript,
+    Tsx,
+}
+
+impl Grammar {
+    pub const ALL: [Grammar; 2] = [Grammar::TypeScript, Grammar::Tsx];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Grammar::TypeScript => "typescript",
+            Grammar::Tsx => "tsx",
+        }
+    }
+
+    pub fn has_jsx(&self) -> bool {
+        matches!(self, Grammar::Tsx)
+    }
+
+    pub fn tree_sitter_language(&self) -> Language {
+        match self {
+            Grammar::TypeScript => LANGUAGE_TYPESCRIPT.into(),
+            Grammar::Tsx => LANGUAGE_TSX.into(),
+        }
+    }
+
+    pub fn create_parser(&self) -> Parser {
+        let mut parser = Parser::new();
+        parser.set_language(&self.tree_sitter_language()).unwrap();
+        parser
+    }
+}
@@ -1,7 +1,4 @@
-use std::time::Instant;
-
 pub use backend::TreeWrapper;
-use helper::get_tree_sitter_edit_from_change;
 use jsonrpc::Result;
 use log::debug;
 use lsp_text_document::FullTextDocument;
@@ -10,28 +7,54 @@ use tower_lsp::{jsonrpc, lsp_types::*, LanguageServer};
 mod backend;
 mod code_action;
 mod completion;
+mod diagnostic;
+mod document;
 mod document_symbol;
 mod helper;
+mod import;
+pub mod indent;
+pub mod language;
 mod notification;
+pub mod position;
+pub mod postfix_context;
 mod query_pattern;
+pub mod query_registry;
+pub mod receiver;
+pub mod template;
+pub mod type_query;
+use backend::initial_workspace_folder_list;
 pub use backend::Backend;
-use tree_sitter::{Parser, Point};
 
-use crate::helper::generate_lsp_range;
-use code_action::get_function_call_action;
-use completion::get_react_completion;
+use crate::document::{spawn_parse_worker, DocumentSnapshot, DocumentState};
+use crate::import::DocumentImport;
+use crate::language::LanguageKind;
+use crate::position::PositionEncoding;
+use crate::query_registry::QueryName;
+use crate::receiver::{postfix_candidate_list, ReceiverText};
+use crate::template::WORKSPACE_TEMPLATE_FILE_NAME_LIST;
+use code_action::{get_extract_component_action, get_function_call_action};
+use completion::{
+    resolve_postfix_item, CompletionCapability, PostfixEnvironment, PostfixRange, ReactImport,
+};
+use document_symbol::get_document_symbol;
 #[tower_lsp::async_trait]
 impl LanguageServer for Backend {
     async fn initialize(&self, params: InitializeParams) -> jsonrpc::Result<InitializeResult> {
-        // *self.workspace_folder.lock().await = params.workspace_folders.unwrap_or(vec![]);
+        *self.workspace_folder.lock().await = initial_workspace_folder_list(&params);
+        let position_encoding = PositionEncoding::negotiate(&params.capabilities);
+        let _ = self
+            .completion_capability
+            .set(CompletionCapability::new(&params.capabilities));
+        let _ = self.position_encoding.set(position_encoding);
         Ok(InitializeResult {
             server_info: None,
             capabilities: ServerCapabilities {
+                position_encoding: Some(position_encoding.kind()),
                 text_document_sync: Some(TextDocumentSyncCapability::Kind(
                     TextDocumentSyncKind::INCREMENTAL,
                 )),
                 completion_provider: Some(CompletionOptions {
-                    resolve_provider: Some(false),
+                    resolve_provider: Some(true),
                     trigger_characters: Some(vec![
                         ".".to_string(),
                         "'".to_string(),
@@ -47,6 +70,7 @@ impl LanguageServer for Backend {
                 }),
 
                 code_action_provider: Some(CodeActionProviderCapability::Simple(true)),
+                document_symbol_provider: Some(OneOf::Left(true)),
 
                 workspace: Some(WorkspaceServerCapabilities {
                     workspace_folders: Some(WorkspaceFoldersServerCapabilities {
@@ -61,7 +85,10 @@ impl LanguageServer for Backend {
     }
 
     async fn initialized(&self, _: InitializedParams) {
+        self.report_query_error().await;
         self.reset_templates().await;
+        self.reload_workspace_templates().await;
+        self.start_type_query().await;
         debug!("initialized!");
     }
 
@@ -69,29 +96,63 @@ impl LanguageServer for Backend {
         Ok(())
     }
 
-    async fn did_change_workspace_folders(&self, _: DidChangeWorkspaceFoldersParams) {
+    async fn did_change_workspace_folders(&self, params: DidChangeWorkspaceFoldersParams) {
+        {
+            let mut workspace_folder = self.workspace_folder.lock().await;
+            workspace_folder.retain(|folder| !params.event.removed.contains(folder));
+            workspace_folder.extend(params.event.added);
+        }
+        self.reload_workspace_templates().await;
         debug!("workspace folders changed!");
     }
 
     async fn did_change_configuration(&self, _: DidChangeConfigurationParams) {
         self.reset_templates().await;
+        self.clear_indent_style(None);
         debug!("configuration changed!");
     }
 
-    async fn did_change_watched_files(&self, _: DidChangeWatchedFilesParams) {
+    async fn did_change_watched_files(&self, params: DidChangeWatchedFilesParams) {
+        let template_file_changed = params.changes.iter().any(|change| {
+            WORKSPACE_TEMPLATE_FILE_NAME_LIST
+                .iter()
+                .any(|file_name| change.uri.path().ends_with(&format!("/{}", file_name)))
+        });
+        if template_file_changed {
+            self.reload_workspace_templates().await;
+        }
+        let editorconfig_changed = params
+            .changes
+            .iter()
+            .any(|change| change.uri.path().ends_with("/.editorconfig"));
+        if editorconfig_changed {
+            self.clear_indent_style(None);
+        }
         debug!("watched files have changed!");
     }
 
     async fn code_action(&self, params: CodeActionParams) -> Result<Option<CodeActionResponse>> {
         let mut code_action_result = CodeActionResponse::new();
         code_action_result.extend(
-            get_function_call_action(&self, params.clone())
+            get_function_call_action(self, params.clone())
+                .await
+                .unwrap_or_default(),
+        );
+        code_action_result.extend(
+            get_extract_component_action(self, params)
                 .await
                 .unwrap_or_default(),
         );
         Ok(Some(code_action_result))
     }
 
+    async fn document_symbol(
+        &self,
+        params: DocumentSymbolParams,
+    ) -> Result<Option<DocumentSymbolResponse>> {
+        Ok(get_document_symbol(self, params).await)
+    }
+
     async fn execute_command(&self, _params: ExecuteCommandParams) -> Result<Option<Value>> {
         debug!("command executed!");
 
@@ -105,59 +166,49 @@ impl LanguageServer for Backend {
             version,
             text,
         } = params.text_document;
-        let tree = self.parser.lock().await.parse(&text, None).unwrap();
-        self.parse_tree_map
-            .lock()
-            .await
-            .insert(uri.to_string(), tree);
-        self.document_map.lock().await.insert(
-            uri.to_string(),
-            FullTextDocument::new(uri, language_id, version as i64, text),
+        let language = LanguageKind::new(&language_id, &uri);
+        let state = DocumentState::new(
+            FullTextDocument::new(uri.clone(), language_id, version as i64, text),
+            version,
+            language,
+        );
+        let notify = state.parse_notify.clone();
+        // opened twice without closing, let the worker of the replaced state exit
+        if let Some(replaced) = self.document_map.insert(uri.clone(), state) {
+            replaced.parse_notify.notify_one();
+        }
+        spawn_parse_worker(
+            self.document_map.clone(),
+            uri,
+            notify,
+            self.client.clone(),
+            self.position_encoding(),
         );
     }
 
     async fn did_change(&self, params: DidChangeTextDocumentParams) {
-        if let Some(document) = self
-            .document_map
-            .lock()
-            .await
-            .get_mut(&params.text_document.uri.to_string())
-        {
-            let mut parser = self.parser.lock().await;
-            let mut parse_tree_map = self.parse_tree_map.lock().await;
-            let changes: Vec<TextDocumentContentChangeEvent> = params
-                .content_changes
-                .into_iter()
-                .map(|change| {
-                    let range = change.range.map(|range| {
-                        generate_lsp_range(
-                            range.start.line as u32,
-                            range.start.character as u32,
-                            range.end.line as u32,
-                            range.end.character as u32,
-                        )
-                    });
-                    TextDocumentContentChangeEvent {
-                        range,
-                        range_length: change.range_length.and_then(|v| Some(v as u32)),
-                        text: change.text,
-                    }
-                })
-                .collect();
-            let version = params.text_document.version;
-
-            let tree = parse_tree_map
-                .get_mut(&params.text_document.uri.to_string())
-                .unwrap();
-            let start = Instant::now();
-            for change in changes {
-                tree.edit(
-                    &get_tree_sitter_edit_from_change(&change, document, version as i64).unwrap(),
+        let uri = params.text_document.uri;
+        let fell_out_of_sync = match self.document_map.get_mut(&uri) {
+            Some(mut state) => {
+                let was_out_of_sync = state.out_of_sync;
+                let in_sync = state.apply_change(
+                    &uri,
+                    params.text_document.version,
+                    params.content_changes,
+                    self.position_encoding(),
                 );
+                !in_sync && !was_out_of_sync
             }
-            debug!("incremental updating: {:?}", start.elapsed());
-            let new_tree = parser.parse(document.rope.to_string(), Some(tree)).unwrap();
-            parse_tree_map.insert(params.text_document.uri.to_string(), new_tree);
+            None => return,
+        };
+        // the protocol has no request for the whole text, a reopen sends it
+        if fell_out_of_sync {
+            self.client
+                .show_message(
+                    MessageType::ERROR,
+                    format!("{} is out of sync, reopen it to continue", uri),
+                )
+                .await;
         }
     }
 
@@ -181,94 +232,133 @@ impl LanguageServer for Backend {
     }
 
     async fn did_close(&self, params: DidCloseTextDocumentParams) {
-        let mut map = self.document_map.lock().await;
-        map.remove(&params.text_document.uri.to_string());
+        // the parse worker exits once it finds the state removed
+        if let Some((_, state)) = self.document_map.remove(&params.text_document.uri) {
+            state.parse_notify.notify_one();
+        }
+        self.clear_indent_style(Some(&params.text_document.uri));
+        self.client
+            .publish_diagnostics(params.text_document.uri, vec![], None)
+            .await;
     }
 
     async fn completion(&self, params: CompletionParams) -> Result<Option<CompletionResponse>> {
         if let Some(_) = params.context {
-            if let Some(document) = self
-                .document_map
-                .lock()
+            if let Some(snapshot) = self
+                .get_snapshot(&params.text_document_position.text_document.uri)
                 .await
-                .get(&params.text_document_position.text_document.uri.to_string())
             {
+                let DocumentSnapshot {
+                    rope,
+                    source,
+                    language,
+                    tree,
+                    ..
+                } = &*snapshot;
                 let pos = params.text_document_position.position.clone();
-                dbg!(&pos);
-                let line = document.rope.line(pos.line as usize);
+                let encoding = self.position_encoding();
+                let completion_capability = self
+                    .completion_capability
+                    .get()
+                    .copied()
+                    .unwrap_or_default();
+                let indent_style = self
+                    .indent_style(&params.text_document_position.text_document.uri)
+                    .await;
+                let cursor_char = match encoding.position_to_char(rope, pos) {
+                    Some(cursor_char) => cursor_char,
+                    None => return Ok(None),
+                };
+                let line_start_char = rope.line_to_char(pos.line as usize);
 
-                let line_text_before_cursor = line.slice(..pos.character as usize).to_string();
+                let line_text_before_cursor = rope.slice(line_start_char..cursor_char).to_string();
                 let before_string = line_text_before_cursor
                     .rfind(".")
                     .and_then(|n| Some(&line_text_before_cursor[n + 1..]));
-                // debug!("before_string:{:?}", before_string);
-                let parser = self.parser.lock().await;
-                let map = self.parse_tree_map.lock().await;
-                let tree = map.get(&params.text_document_position.text_document.uri.to_string());
-
-                match tree {
-                    Some(tree) if before_string.is_some() => {
-                        let completion_keyword = before_string.unwrap();
+                match before_string {
+                    Some(completion_keyword) => {
                         let root = tree.root_node();
-                        let dot = params.text_document_position.position;
-                        let before_dot = Position::new(
-                            dot.line,
-                            dot.character
-                                .wrapping_sub(completion_keyword.len() as u32 + 2),
-                        );
-                        // this is based bytes index
-                        // let byte_index_start = document.rope.line_to_byte(before_dot.line as usize);
-                        let char_index = document.rope.line_to_char(before_dot.line as usize)
-                            + before_dot.character as usize;
-                        let byte_index = document.rope.char_to_byte(char_index);
-                        let before_dot_node =
-                            root.named_descendant_for_byte_range(byte_index, byte_index);
-
-                        if let Some(mut node) = before_dot_node {
-                            let end_index = node.end_byte();
-                            while let Some(parent) = node.parent() {
-                                if !node.is_error()
-                                    && parent.kind().contains("expression")
-                                    && parent.end_byte() == end_index
-                                {
-                                    node = parent;
-                                } else {
-                                    break;
-                                }
-                            }
-                            let replace_range = generate_lsp_range(
-                                node.start_position().row as u32,
-                                node.start_position().column as u32,
-                                dot.line,
-                                dot.character,
+                        let dot_byte = rope.char_to_byte(line_start_char)
+                            + line_text_before_cursor.len()
+                            - completion_keyword.len()
+                            - 1;
+                        let cursor_byte = rope.char_to_byte(cursor_char);
+                        let candidate_list =
+                            postfix_candidate_list(*language, root, source, dot_byte, cursor_byte);
+                        let environment = PostfixEnvironment {
+                            capability: completion_capability,
+                            indent_style,
+                            document_import: DocumentImport::new(
+                                self.query_registry
+                                    .get(*language, QueryName::ImportStatement),
+                                rope,
+                                encoding,
+                                source,
+                                root,
+                            ),
+                            react_import: ReactImport::new(
+                                self.query_registry.get(*language, QueryName::ReactImport),
+                                source,
+                                root,
+                            ),
+                        };
+                        let byte_range_list = candidate_list
+                            .iter()
+                            .map(|candidate| candidate.byte_range.clone())
+                            .collect::<Vec<_>>();
+                        let type_info_list = self
+                            .query_type_list(
+                                &params.text_document_position.text_document.uri,
+                                rope,
+                                source,
+                                &byte_range_list,
+                            )
+                            .await;
+                        let mut item_list = vec![];
+                        for (i, candidate) in candidate_list.iter().enumerate() {
+                            let postfix_range = PostfixRange::new(
+                                rope,
+                                encoding,
+                                candidate.byte_range.start,
+                                dot_byte,
+                                cursor_byte,
+                                &candidate.scope,
+                                &environment,
                             );
-                            let source = document.rope.to_string();
-
-                            let res = get_react_completion(
-                                &source[node.byte_range()],
-                                &source,
-                                &replace_range,
-                                tree,
-                                parser,
+                            let receiver = ReceiverText::new(
+                                source,
+                                candidate.kind,
+                                candidate.byte_range.clone(),
                             );
                             let mut template_item_list = self.get_template_completion_item_list(
-                                &source[node.byte_range()],
-                                &replace_range,
+                                &params.text_document_position.text_document.uri,
+                                &receiver,
+                                candidate.kind,
+                                *language,
+                                &postfix_range,
+                                candidate.context,
+                                type_info_list[i].as_ref(),
                             );
-                            template_item_list.extend(self.get_snippet_completion_item_list(
-                                &source[node.byte_range()],
-                                &replace_range,
-                            ));
-                            template_item_list.extend(res);
-                            return Ok(Some(CompletionResponse::Array(template_item_list)));
+                            // the same template for every candidate, told apart by the preview
+                            if candidate_list.len() > 1 {
+                                for item in template_item_list.iter_mut() {
+                                    item.label_details = Some(CompletionItemLabelDetails {
+                                        detail: None,
+                                        description: Some(candidate.preview.clone()),
+                                    });
+                                    item.sort_text = Some(format!("{}{:02}", item.label, i));
+                                }
+                            }
+                            item_list.extend(template_item_list);
+                        }
+                        if !item_list.is_empty() {
+                            return Ok(Some(CompletionResponse::Array(item_list)));
                         }
                     }
-                    Some(tree) => {
+                    None => {
                         let root = tree.root_node();
-                        let node = root.named_descendant_for_point_range(
-                            Point::new(pos.line as usize, pos.character as usize),
-                            Point::new(pos.line as usize, pos.character as usize),
-                        );
+                        let cursor_byte = rope.char_to_byte(cursor_char);
+                        let node = root.named_descendant_for_byte_range(cursor_byte, cursor_byte);
                         if let Some(node) = node {
                             if matches!(node.kind(), "string" | "template_string") {
                                 let attribute = {
@@ -325,10 +415,13 @@ impl LanguageServer for Backend {
                             };
                         }
                     }
-                    _ => {}
                 };
             }
         }
         Ok(None)
     }
+
+    async fn completion_resolve(&self, item: CompletionItem) -> Result<CompletionItem> {
+        Ok(resolve_postfix_item(item))
+    }
 }
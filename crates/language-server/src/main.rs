@@ -1,13 +1,8 @@
 use tower_lsp::{LspService, Server};
 
-use std::{
-    collections::HashMap,
-    sync::{Arc, Mutex as StdMutex},
-};
-use tjs_language_server::Backend;
-use tokio::sync::Mutex;
-
-use tree_sitter_typescript::LANGUAGE_TSX;
+use dashmap::DashMap;
+use std::sync::{Arc, Mutex as StdMutex};
+use tjs_language_server::{query_registry::QueryRegistry, Backend};
 
 #[tokio::main]
 async fn main() {
@@ -15,21 +10,18 @@ async fn main() {
     let stdin = tokio::io::stdin();
     let stdout = tokio::io::stdout();
 
-    let mut tsx_parser = tree_sitter::Parser::new();
-    tsx_parser.set_language(&LANGUAGE_TSX.into()).unwrap();
-
-    let (service, socket) = LspService::new(|client| {
-        let document_map = Mutex::new(HashMap::new());
-        let parse_tree_map = Mutex::new(HashMap::new());
+    let (service, socket) = LspService::build(|client| {
+        let document_map = Arc::new(DashMap::new());
         let postfix_template_list = Arc::new(StdMutex::new(vec![]));
         Backend::new(
             client,
             document_map,
-            Mutex::new(tsx_parser),
             postfix_template_list,
-            parse_tree_map,
+            QueryRegistry::new(),
         )
-    });
+    })
+    .custom_method("tjs-postfix/ast-preview", Backend::ast_preview)
+    .finish();
 
     let server = Server::new(stdin, stdout, socket).serve(service);
 
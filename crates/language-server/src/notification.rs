@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use tree_sitter::{Point, TreeCursor};
 #[derive(Debug, Deserialize, Serialize)]
 pub struct CustomNotificationParams {
     title: String,
@@ -6,8 +7,96 @@ pub struct CustomNotificationParams {
 }
 
 #[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct AstPreviewRequestParams {
     pub(crate) path: String,
+    /// also return the tree as json, the pretty printed string is always returned
+    #[serde(default)]
+    pub(crate) include_tree: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AstPreviewResponse {
+    pub(crate) path: String,
+    pub(crate) ast: String,
+    pub(crate) tree: Option<AstNode>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AstNode {
+    kind: String,
+    field_name: Option<String>,
+    start_byte: usize,
+    end_byte: usize,
+    start: AstPoint,
+    end: AstPoint,
+    is_error: bool,
+    is_missing: bool,
+    children: Vec<AstNode>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AstPoint {
+    row: usize,
+    column: usize,
+}
+
+impl From<Point> for AstPoint {
+    fn from(point: Point) -> Self {
+        AstPoint {
+            row: point.row,
+            column: point.column,
+        }
+    }
+}
+
+impl AstNode {
+    /// build the json tree of the node under the cursor, only named nodes are kept,
+    /// same as `TreeWrapper`, the cursor is back on the node after
+    pub(crate) fn from_cursor(cursor: &mut TreeCursor) -> Self {
+        // the ancestors of the cursor, each collecting its named children
+        let mut stack = vec![AstNode::leaf(cursor)];
+        if !cursor.goto_first_child() {
+            return stack.pop().unwrap();
+        }
+        loop {
+            if cursor.node().is_named() {
+                stack.push(AstNode::leaf(cursor));
+                if cursor.goto_first_child() {
+                    continue;
+                }
+                let node = stack.pop().unwrap();
+                stack.last_mut().unwrap().children.push(node);
+            }
+            // every child of the top of the stack is visited, it is complete
+            while !cursor.goto_next_sibling() {
+                cursor.goto_parent();
+                let node = stack.pop().unwrap();
+                match stack.last_mut() {
+                    Some(parent) => parent.children.push(node),
+                    None => return node,
+                }
+            }
+        }
+    }
+
+    /// the node under the cursor without children
+    fn leaf(cursor: &TreeCursor) -> Self {
+        let node = cursor.node();
+        AstNode {
+            kind: node.kind().to_string(),
+            field_name: cursor.field_name().map(|name| name.to_string()),
+            start_byte: node.start_byte(),
+            end_byte: node.end_byte(),
+            start: node.start_position().into(),
+            end: node.end_position().into(),
+            is_error: node.is_error(),
+            is_missing: node.is_missing(),
+            children: vec![],
+        }
+    }
 }
 
 impl CustomNotificationParams {
@@ -20,3 +109,65 @@ impl CustomNotificationParams {
 }
 
 pub enum CustomNotification {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::language::LanguageKind;
+
+    fn ast(source: &str) -> AstNode {
+        let tree = LanguageKind::TypeScript
+            .parse_with(&mut |byte_index, _| &source.as_bytes()[byte_index..], None)
+            .unwrap();
+        let node = AstNode::from_cursor(&mut tree.walk());
+        node
+    }
+
+    /// `kind` or `field: kind`, children indented
+    fn outline(node: &AstNode, level: usize, line_list: &mut Vec<String>) {
+        let field = node
+            .field_name
+            .as_ref()
+            .map(|name| format!("{}: ", name))
+            .unwrap_or_default();
+        line_list.push(format!("{}{}{}", "  ".repeat(level), field, node.kind));
+        for child in &node.children {
+            outline(child, level + 1, line_list);
+        }
+    }
+
+    #[test]
+    fn named_nodes_in_order() {
+        let mut line_list = vec![];
+        outline(&ast("a(b);\nc;"), 0, &mut line_list);
+        assert_eq!(
+            line_list,
+            [
+                "program",
+                "  expression_statement",
+                "    call_expression",
+                "      function: identifier",
+                "      arguments: arguments",
+                "        identifier",
+                "  expression_statement",
+                "    identifier",
+            ]
+        );
+    }
+
+    #[test]
+    fn deep_tree() {
+        let depth = 2000;
+        let source = format!("{}a{};", "(".repeat(depth), ")".repeat(depth));
+        let mut node = &ast(&source);
+        let mut level = 0;
+        while let Some(child) = node.children.first() {
+            node = child;
+            level += 1;
+        }
+        assert_eq!(node.kind, "identifier");
+        assert_eq!(node.end_byte, depth + 1);
+        // program, expression_statement and every parenthesized_expression
+        assert_eq!(level, depth + 2);
+    }
+}
@@ -0,0 +1,269 @@
+use ropey::Rope;
+use tower_lsp::lsp_types::{ClientCapabilities, Position, PositionEncodingKind, Range};
+use tree_sitter::{Node, Point};
+
+/// The unit of `Position::character` agreed with the client in `initialize`.
+///
+/// There are three coordinate systems in the server, all conversions between them go
+/// through here:
+/// - lsp `Position`, line and a column counted in the negotiated unit
+/// - rope char index, used to apply edits to the document
+/// - tree-sitter byte offset and `Point`, whose column is counted in bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PositionEncoding {
+    Utf8,
+    #[default]
+    Utf16,
+    Utf32,
+}
+
+impl PositionEncoding {
+    /// pick the first encoding the client prefers, utf-16 is mandatory for all clients
+    pub fn negotiate(capabilities: &ClientCapabilities) -> Self {
+        capabilities
+            .general
+            .as_ref()
+            .and_then(|general| general.position_encodings.as_ref())
+            .and_then(|kind_list| kind_list.iter().find_map(Self::from_kind))
+            .unwrap_or_default()
+    }
+
+    pub fn from_kind(kind: &PositionEncodingKind) -> Option<Self> {
+        match kind.as_str() {
+            "utf-8" => Some(PositionEncoding::Utf8),
+            "utf-16" => Some(PositionEncoding::Utf16),
+            "utf-32" => Some(PositionEncoding::Utf32),
+            _ => None,
+        }
+    }
+
+    pub fn kind(&self) -> PositionEncodingKind {
+        match self {
+            PositionEncoding::Utf8 => PositionEncodingKind::UTF8,
+            PositionEncoding::Utf16 => PositionEncodingKind::UTF16,
+            PositionEncoding::Utf32 => PositionEncodingKind::UTF32,
+        }
+    }
+
+    /// length of `text` in the unit of this encoding
+    pub fn text_len(&self, text: &str) -> usize {
+        match self {
+            PositionEncoding::Utf8 => text.len(),
+            PositionEncoding::Utf16 => text.encode_utf16().count(),
+            PositionEncoding::Utf32 => text.chars().count(),
+        }
+    }
+
+    /// `None` if the line is out of the document, a column past the end of line is
+    /// clamped to the end of line as the spec requires
+    pub fn position_to_char(&self, rope: &Rope, position: Position) -> Option<usize> {
+        let line = position.line as usize;
+        if line >= rope.len_lines() {
+            return None;
+        }
+        let line_start_char = rope.line_to_char(line);
+        let line_end_char = line_start_char + line_content_len_chars(rope, line);
+        let column = position.character as usize;
+        let char_index = match self {
+            PositionEncoding::Utf8 => {
+                let line_end_byte = rope.char_to_byte(line_end_char);
+                rope.byte_to_char((rope.line_to_byte(line) + column).min(line_end_byte))
+            }
+            PositionEncoding::Utf16 => {
+                let line_end_cu = rope.char_to_utf16_cu(line_end_char);
+                rope.utf16_cu_to_char(
+                    (rope.char_to_utf16_cu(line_start_char) + column).min(line_end_cu),
+                )
+            }
+            PositionEncoding::Utf32 => line_start_char + column,
+        };
+        Some(char_index.min(line_end_char))
+    }
+
+    pub fn position_to_byte(&self, rope: &Rope, position: Position) -> Option<usize> {
+        self.position_to_char(rope, position)
+            .map(|char_index| rope.char_to_byte(char_index))
+    }
+
+    pub fn char_to_position(&self, rope: &Rope, char_index: usize) -> Position {
+        let char_index = char_index.min(rope.len_chars());
+        let line = rope.char_to_line(char_index);
+        let line_start_char = rope.line_to_char(line);
+        let column = match self {
+            PositionEncoding::Utf8 => {
+                rope.char_to_byte(char_index) - rope.char_to_byte(line_start_char)
+            }
+            PositionEncoding::Utf16 => {
+                rope.char_to_utf16_cu(char_index) - rope.char_to_utf16_cu(line_start_char)
+            }
+            PositionEncoding::Utf32 => char_index - line_start_char,
+        };
+        Position::new(line as u32, column as u32)
+    }
+
+    pub fn byte_to_position(&self, rope: &Rope, byte_index: usize) -> Position {
+        let byte_index = byte_index.min(rope.len_bytes());
+        self.char_to_position(rope, rope.byte_to_char(byte_index))
+    }
+
+    /// `Point::column` is a byte offset in the row
+    pub fn point_to_position(&self, rope: &Rope, point: Point) -> Position {
+        let line_start_byte = rope.try_line_to_byte(point.row).unwrap_or(rope.len_bytes());
+        self.byte_to_position(rope, line_start_byte + point.column)
+    }
+
+    pub fn byte_range_to_range(&self, rope: &Rope, start_byte: usize, end_byte: usize) -> Range {
+        Range::new(
+            self.byte_to_position(rope, start_byte),
+            self.byte_to_position(rope, end_byte),
+        )
+    }
+
+    pub fn node_range(&self, rope: &Rope, node: Node) -> Range {
+        self.byte_range_to_range(rope, node.start_byte(), node.end_byte())
+    }
+
+    /// convert a range of this encoding to the one counted in chars, which is what
+    /// `FullTextDocument` expects
+    pub fn range_to_char_range(&self, rope: &Rope, range: Range) -> Option<Range> {
+        let to_char_position = |position| {
+            let char_index = self.position_to_char(rope, position)?;
+            Some(PositionEncoding::Utf32.char_to_position(rope, char_index))
+        };
+        Some(Range::new(
+            to_char_position(range.start)?,
+            to_char_position(range.end)?,
+        ))
+    }
+}
+
+/// the tree-sitter point of a byte offset
+pub fn byte_to_point(rope: &Rope, byte_index: usize) -> Point {
+    let byte_index = byte_index.min(rope.len_bytes());
+    let row = rope.byte_to_line(byte_index);
+    Point::new(row, byte_index - rope.line_to_byte(row))
+}
+
+/// chars of the line without the trailing line break
+fn line_content_len_chars(rope: &Rope, line: usize) -> usize {
+    let slice = rope.line(line);
+    let mut len = slice.len_chars();
+    if len > 0 && slice.char(len - 1) == '\n' {
+        len -= 1;
+    }
+    if len > 0 && slice.char(len - 1) == '\r' {
+        len -= 1;
+    }
+    len
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [PositionEncoding; 3] = [
+        PositionEncoding::Utf8,
+        PositionEncoding::Utf16,
+        PositionEncoding::Utf32,
+    ];
+
+    #[test]
+    fn round_trip() {
+        let rope = Rope::from_str("a😀b\n中文x\r\nend");
+        for encoding in ALL {
+            for char_index in 0..=rope.len_chars() {
+                let position = encoding.char_to_position(&rope, char_index);
+                let back = encoding.position_to_char(&rope, position).unwrap();
+                // the `\n` of `\r\n` is the end of the line as well
+                let expected = if rope.char(char_index.saturating_sub(1)) == '\r' {
+                    char_index - 1
+                } else {
+                    char_index
+                };
+                assert_eq!(back, expected, "{:?} {:?}", encoding, position);
+                let byte_index = rope.char_to_byte(char_index);
+                let position = encoding.byte_to_position(&rope, byte_index);
+                assert_eq!(encoding.char_to_position(&rope, char_index), position);
+            }
+        }
+    }
+
+    #[test]
+    fn column_in_each_encoding() {
+        let rope = Rope::from_str("a😀b\n中文x\r\nend");
+        // `b` and `x`
+        let table = [
+            (PositionEncoding::Utf8, 5, 6),
+            (PositionEncoding::Utf16, 3, 2),
+            (PositionEncoding::Utf32, 2, 2),
+        ];
+        for (encoding, b_column, x_column) in table {
+            assert_eq!(
+                encoding.char_to_position(&rope, 2),
+                Position::new(0, b_column)
+            );
+            assert_eq!(
+                encoding.char_to_position(&rope, 6),
+                Position::new(1, x_column)
+            );
+            assert_eq!(
+                encoding.position_to_char(&rope, Position::new(0, b_column)),
+                Some(2)
+            );
+            assert_eq!(
+                encoding.position_to_char(&rope, Position::new(1, x_column)),
+                Some(6)
+            );
+            assert_eq!(encoding.text_len("a😀b"), b_column as usize + 1);
+        }
+    }
+
+    #[test]
+    fn position_past_the_end() {
+        let rope = Rope::from_str("a😀b\n中文x\r\nend");
+        for encoding in ALL {
+            // a column past the end of line is the end of line
+            assert_eq!(
+                encoding.position_to_char(&rope, Position::new(0, 100)),
+                Some(3)
+            );
+            assert_eq!(
+                encoding.position_to_char(&rope, Position::new(1, 100)),
+                Some(7)
+            );
+            assert_eq!(
+                encoding.position_to_char(&rope, Position::new(2, 100)),
+                Some(12)
+            );
+            // a line past the end of document is not in it
+            assert_eq!(encoding.position_to_char(&rope, Position::new(3, 0)), None);
+            assert_eq!(encoding.byte_to_position(&rope, 100), Position::new(2, 3));
+        }
+    }
+
+    #[test]
+    fn negotiate() {
+        let capabilities = |kind_list: Vec<PositionEncodingKind>| ClientCapabilities {
+            general: Some(tower_lsp::lsp_types::GeneralClientCapabilities {
+                position_encodings: Some(kind_list),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let table = [
+            (vec![], PositionEncoding::Utf16),
+            (vec!["utf-7".into()], PositionEncoding::Utf16),
+            (
+                vec!["utf-32".into(), "utf-8".into()],
+                PositionEncoding::Utf32,
+            ),
+            (vec!["utf-7".into(), "utf-8".into()], PositionEncoding::Utf8),
+        ];
+        for (kind_list, expected) in table {
+            assert_eq!(
+                PositionEncoding::negotiate(&capabilities(kind_list)),
+                expected
+            );
+        }
+    }
+}
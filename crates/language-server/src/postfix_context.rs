@@ -0,0 +1,177 @@
+use serde::{Deserialize, Serialize};
+use tree_sitter::Node;
+
+/// Syntactic position of a postfix expression `receiver.keyword`, a template is only
+/// offered where its output is valid
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "camelCase")]
+pub enum PostfixContext {
+    /// the whole expression of an expression statement, e.g. `foo.if`
+    Statement,
+    /// any other place an expression is expected, e.g. `bar(foo.not)`
+    Expression,
+    /// `{foo.map}` directly in the children of a jsx element
+    JsxChild,
+    /// any other place in the `{...}` of jsx, e.g. `{a ? items.map : null}`, templates of
+    /// `expression` are offered here too
+    JsxExpression,
+    /// `<A />.frag` directly in the children of a jsx element, where the postfix is jsx text
+    JsxText,
+    /// e.g. `let a: Foo.arr`
+    Type,
+}
+
+impl PostfixContext {
+    /// whether a template of `template_context` is offered in this context
+    pub fn matches(self, template_context: PostfixContext) -> bool {
+        self == template_context
+            || (self == PostfixContext::JsxExpression
+                && template_context == PostfixContext::Expression)
+    }
+
+    /// classify by the ancestors of `receiver`, `postfix_end_byte` is the end of the keyword
+    /// after the dot
+    pub fn classify(receiver: Node, postfix_end_byte: usize) -> Self {
+        // the keyword is parsed as a part of the tree too, e.g. `foo.if` is a
+        // `member_expression` and `foo.` is an `expression_statement` with an `ERROR` in it
+        let mut node = receiver;
+        while node.kind() != "expression_statement" {
+            match node.parent() {
+                Some(parent)
+                    if parent.start_byte() == receiver.start_byte()
+                        && parent.end_byte() <= postfix_end_byte =>
+                {
+                    node = parent
+                }
+                _ => break,
+            }
+        }
+        if is_type_node(node) {
+            return PostfixContext::Type;
+        }
+        if node.kind() == "expression_statement" {
+            return PostfixContext::Statement;
+        }
+        match node.parent() {
+            Some(parent) if parent.kind() == "expression_statement" => PostfixContext::Statement,
+            Some(parent)
+                if parent.kind() == "jsx_expression"
+                    && matches!(parent.parent(), Some(element) if element.kind() == "jsx_element") =>
+            {
+                PostfixContext::JsxChild
+            }
+            Some(parent)
+                if parent.kind() == "jsx_element"
+                    && matches!(node.kind(), "jsx_element" | "jsx_self_closing_element") =>
+            {
+                PostfixContext::JsxText
+            }
+            _ if is_in_jsx_expression(node) => PostfixContext::JsxExpression,
+            _ => PostfixContext::Expression,
+        }
+    }
+}
+
+fn is_in_jsx_expression(node: Node) -> bool {
+    let mut node = node.parent();
+    while let Some(current) = node {
+        if current.kind() == "jsx_expression" {
+            return true;
+        }
+        node = current.parent();
+    }
+    false
+}
+
+fn is_type_node(node: Node) -> bool {
+    let kind = node.kind();
+    kind.ends_with("_type")
+        || matches!(
+            kind,
+            "type_identifier" | "nested_type_identifier" | "type_annotation"
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::language::LanguageKind;
+
+    /// the context of the first `receiver` in `code`, followed by `.keyword`
+    fn classify(language: LanguageKind, code: &str, receiver: &str) -> PostfixContext {
+        let bytes = code.as_bytes();
+        let tree = language
+            .parse_with(
+                &mut |byte_index, _| bytes.get(byte_index..).unwrap_or_default(),
+                None,
+            )
+            .unwrap();
+        let start_byte = code.find(receiver).unwrap();
+        let end_byte = start_byte + receiver.len();
+        let mut node = tree
+            .root_node()
+            .descendant_for_byte_range(start_byte, end_byte)
+            .unwrap();
+        while node.byte_range() != (start_byte..end_byte) {
+            node = node.parent().unwrap();
+        }
+        let postfix_end_byte = end_byte
+            + 1
+            + code[end_byte + 1..]
+                .chars()
+                .take_while(|c| c.is_alphanumeric())
+                .count();
+        PostfixContext::classify(node, postfix_end_byte)
+    }
+
+    #[test]
+    fn classify_receiver() {
+        let typescript = LanguageKind::TypeScript;
+        let tsx = LanguageKind::TypeScriptReact;
+        for (language, code, receiver, context) in [
+            (typescript, "foo.if", "foo", PostfixContext::Statement),
+            (
+                typescript,
+                "bar(foo.not)",
+                "foo",
+                PostfixContext::Expression,
+            ),
+            (
+                typescript,
+                "return foo.not",
+                "foo",
+                PostfixContext::Expression,
+            ),
+            (typescript, "let a: Foo.arr", "Foo", PostfixContext::Type),
+            (tsx, "<div>{foo.map}</div>", "foo", PostfixContext::JsxChild),
+            (
+                tsx,
+                "<div title={foo.and} />",
+                "foo",
+                PostfixContext::JsxExpression,
+            ),
+            (
+                tsx,
+                "<div>{a && foo.map}</div>",
+                "foo",
+                PostfixContext::JsxExpression,
+            ),
+            (
+                tsx,
+                "<div>\n  <A />.frag\n</div>",
+                "<A />",
+                PostfixContext::JsxText,
+            ),
+        ] {
+            assert_eq!(classify(language, code, receiver), context, "{}", code);
+        }
+    }
+
+    #[test]
+    fn jsx_expression_matches_expression_templates() {
+        assert!(PostfixContext::JsxExpression.matches(PostfixContext::Expression));
+        assert!(PostfixContext::JsxExpression.matches(PostfixContext::JsxExpression));
+        assert!(!PostfixContext::JsxChild.matches(PostfixContext::Expression));
+        assert!(!PostfixContext::Expression.matches(PostfixContext::JsxExpression));
+    }
+}
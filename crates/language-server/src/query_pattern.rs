@@ -20,19 +20,28 @@ pub const LOCAL_VARIABLE_QUERY: &str = r#"
 ) 
             "#;
 
-pub const REACT_NAME_SPACE_IMPORT: &str = r#"
+pub const REACT_IMPORT: &str = r#"
 (import_statement
-  (import_clause
-    (namespace_import (identifier) @namespace)
-  )
+  (import_clause) @clause
   source: (string
     (string_fragment) @source
   )
-  
-  (#match? @source "react")
+  (#match? @source "^(react|preact/hooks|preact/compat)$")
 )
 "#;
 
+pub const IMPORT_STATEMENT: &str = r#"
+(import_statement
+  source: (string
+    (string_fragment) @source
+  )
+) @statement
+"#;
+
+pub const IDENTIFIER_QUERY_PATTERN: &str = r#"[(identifier) (shorthand_property_identifier)] @a"#;
+
+pub const JSX_EXPRESSION_QUERY_PATTERN: &str = r#"(jsx_expression) @a"#;
+
 pub const FUNCTION_LIKE_DECLARATION: &str = r#"
 (program
     [
@@ -44,7 +53,7 @@ pub const FUNCTION_LIKE_DECLARATION: &str = r#"
             (variable_declarator
                 name: (identifier) @c
                 value: [
-                    (function)
+                    (function_expression)
                     (arrow_function)
                 ]
             )
@@ -0,0 +1,99 @@
+use std::collections::HashMap;
+
+use tree_sitter::Query;
+
+use crate::{
+    language::{Grammar, LanguageKind},
+    query_pattern::{
+        DOCUMENT_SYMBOL_QUERY_PATTERN, FUNCTION_LIKE_DECLARATION, IDENTIFIER_QUERY_PATTERN,
+        IMPORT_STATEMENT, JSX_EXPRESSION_QUERY_PATTERN, LOCAL_VARIABLE_QUERY, REACT_IMPORT,
+    },
+};
+
+/// Every query pattern of `query_pattern`, used as the key of `QueryRegistry`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum QueryName {
+    DocumentSymbol,
+    LocalVariable,
+    ReactImport,
+    ImportStatement,
+    FunctionLikeDeclaration,
+    Identifier,
+    JsxExpression,
+}
+
+impl QueryName {
+    pub const ALL: [QueryName; 7] = [
+        QueryName::DocumentSymbol,
+        QueryName::LocalVariable,
+        QueryName::ReactImport,
+        QueryName::ImportStatement,
+        QueryName::FunctionLikeDeclaration,
+        QueryName::Identifier,
+        QueryName::JsxExpression,
+    ];
+
+    pub fn pattern(&self) -> &'static str {
+        match self {
+            QueryName::DocumentSymbol => DOCUMENT_SYMBOL_QUERY_PATTERN,
+            QueryName::LocalVariable => LOCAL_VARIABLE_QUERY,
+            QueryName::ReactImport => REACT_IMPORT,
+            QueryName::ImportStatement => IMPORT_STATEMENT,
+            QueryName::FunctionLikeDeclaration => FUNCTION_LIKE_DECLARATION,
+            QueryName::Identifier => IDENTIFIER_QUERY_PATTERN,
+            QueryName::JsxExpression => JSX_EXPRESSION_QUERY_PATTERN,
+        }
+    }
+
+    /// patterns that match jsx nodes could only be compiled against a grammar with jsx
+    pub fn requires_jsx(&self) -> bool {
+        matches!(self, QueryName::DocumentSymbol | QueryName::JsxExpression)
+    }
+}
+
+/// All queries compiled once per grammar at startup, instead of on every request
+pub struct QueryRegistry {
+    query_map: HashMap<(Grammar, QueryName), Query>,
+    pub(crate) error_list: Vec<String>,
+}
+
+impl QueryRegistry {
+    pub fn new() -> Self {
+        let mut query_map = HashMap::new();
+        let mut error_list = vec![];
+        for grammar in Grammar::ALL {
+            let language = grammar.tree_sitter_language();
+            for name in QueryName::ALL {
+                if name.requires_jsx() && !grammar.has_jsx() {
+                    continue;
+                }
+                match Query::new(&language, name.pattern()) {
+                    Ok(query) => {
+                        query_map.insert((grammar, name), query);
+                    }
+                    Err(err) => error_list.push(format!(
+                        "failed to compile query {:?} for {}: {}",
+                        name,
+                        grammar.name(),
+                        err
+                    )),
+                }
+            }
+        }
+        Self {
+            query_map,
+            error_list,
+        }
+    }
+
+    /// `None` if the query failed to compile or is not applicable to the language
+    pub fn get(&self, language: LanguageKind, name: QueryName) -> Option<&Query> {
+        self.query_map.get(&(language.grammar(), name))
+    }
+}
+
+impl Default for QueryRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
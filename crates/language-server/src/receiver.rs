@@ -0,0 +1,761 @@
+use std::borrow::Cow;
+use std::ops::Range;
+
+use tree_sitter::{Node, Tree};
+
+use crate::language::LanguageKind;
+use crate::postfix_context::PostfixContext;
+use crate::template::CompiledTemplate;
+
+/// An expression a postfix template may apply to, e.g. both `b` and `a + b` in `a + b.if`
+#[derive(Debug, Clone)]
+pub struct ReceiverCandidate<'tree> {
+    /// the node the candidate is derived from, it may contain the `.keyword` when the
+    /// postfix is the last operand, e.g. the `await_expression` of `await foo().then`
+    pub node: Node<'tree>,
+    /// the candidate without the dot and keyword
+    pub byte_range: Range<usize>,
+}
+
+impl ReceiverCandidate<'_> {
+    /// the first line of the candidate, shortened to tell candidates apart in completion
+    pub fn preview(&self, source: &str) -> String {
+        const MAX_PREVIEW_LEN: usize = 32;
+        let text = &source[self.byte_range.clone()];
+        let first_line = text.lines().next().unwrap_or_default().trim_end();
+        if first_line.len() < text.len() || first_line.chars().count() > MAX_PREVIEW_LEN {
+            let mut preview = first_line.chars().take(MAX_PREVIEW_LEN).collect::<String>();
+            preview.push('…');
+            preview
+        } else {
+            first_line.to_string()
+        }
+    }
+}
+
+/// The text a template fills `${expr}` with
+#[derive(Debug, Clone)]
+pub struct ReceiverText<'a> {
+    /// as it is in the document
+    pub text: &'a str,
+    /// a type binding looser than `T[]` and `keyof T` is parenthesized, e.g. `(A | B)`
+    pub operand: Cow<'a, str>,
+}
+
+impl<'a> ReceiverText<'a> {
+    pub fn new(source: &'a str, kind: &str, byte_range: Range<usize>) -> Self {
+        let text = &source[byte_range];
+        let operand = if matches!(
+            kind,
+            "union_type"
+                | "intersection_type"
+                | "function_type"
+                | "constructor_type"
+                | "conditional_type"
+        ) {
+            Cow::Owned(format!("({})", text))
+        } else {
+            Cow::Borrowed(text)
+        };
+        Self { text, operand }
+    }
+
+    /// `(A | B)[]` but `Promise<A | B>`
+    pub fn for_template(&self, template: &CompiledTemplate) -> &str {
+        if template.expr_is_operand {
+            &self.operand
+        } else {
+            self.text
+        }
+    }
+}
+
+/// A candidate with what completion needs of its node, nodes can't be held across the type
+/// queries
+#[derive(Debug, Clone)]
+pub struct PostfixCandidate {
+    pub context: PostfixContext,
+    pub kind: &'static str,
+    pub preview: String,
+    pub byte_range: Range<usize>,
+    pub scope: EnclosingScope,
+}
+
+/// Candidates of the postfix from `dot_byte` to `cursor_byte` in `root` of `source`, from the
+/// innermost to the outermost. A copy of `source` without the postfix is parsed if the
+/// candidates in `root` are not reliable
+pub fn postfix_candidate_list(
+    language: LanguageKind,
+    root: Node,
+    source: &str,
+    dot_byte: usize,
+    cursor_byte: usize,
+) -> Vec<PostfixCandidate> {
+    if is_dot_in_literal(root, dot_byte) {
+        return vec![];
+    }
+    let bytes = source.as_bytes();
+    let candidate_list = receiver_candidate_list(root, bytes, dot_byte, cursor_byte);
+    let postfix_candidate =
+        |candidate: &ReceiverCandidate, postfix_end_byte, shift| PostfixCandidate {
+            context: PostfixContext::classify(candidate.node, postfix_end_byte),
+            kind: candidate.node.kind(),
+            preview: candidate.preview(source),
+            byte_range: candidate.byte_range.clone(),
+            scope: EnclosingScope::new(candidate.node, bytes, dot_byte, shift),
+        };
+    if needs_recovery(root, dot_byte, cursor_byte, &candidate_list) {
+        if let Some(tree) = reparse_without_postfix(language, source, dot_byte, cursor_byte) {
+            let recovered_candidate_list =
+                receiver_candidate_list(tree.root_node(), bytes, dot_byte, dot_byte);
+            if !recovered_candidate_list.is_empty() {
+                // the postfix ends at the dot in the recovered tree
+                return recovered_candidate_list
+                    .iter()
+                    .map(|candidate| postfix_candidate(candidate, dot_byte, cursor_byte - dot_byte))
+                    .collect();
+            }
+        }
+    }
+    candidate_list
+        .iter()
+        .map(|candidate| postfix_candidate(candidate, cursor_byte, 0))
+        .collect()
+}
+
+/// Candidate receivers of the postfix whose dot is at `dot_byte`, from the innermost to the
+/// outermost
+fn receiver_candidate_list<'tree>(
+    root: Node<'tree>,
+    source: &[u8],
+    dot_byte: usize,
+    cursor_byte: usize,
+) -> Vec<ReceiverCandidate<'tree>> {
+    match postfix_member_expression(root, dot_byte, cursor_byte) {
+        Some((postfix, receiver)) => member_candidate_list(postfix, receiver),
+        None => fallback_candidate_list(root, source, dot_byte),
+    }
+}
+
+/// whether the dot or the innermost candidate is parsed in an `ERROR`, or the property after
+/// the dot is not the keyword, the candidates are not reliable then, e.g. `a + foo.bar.`
+/// followed by another statement
+fn needs_recovery(
+    root: Node,
+    dot_byte: usize,
+    cursor_byte: usize,
+    candidate_list: &[ReceiverCandidate],
+) -> bool {
+    let dot = root.descendant_for_byte_range(dot_byte, dot_byte + 1);
+    dot.is_none_or(is_in_error)
+        || dot.is_some_and(|dot| is_next_token_property(dot, cursor_byte))
+        || candidate_list
+            .first()
+            .is_none_or(|candidate| is_in_error(candidate.node))
+}
+
+/// `foo.bar.` followed by `baz();` in the next line is parsed as `foo.bar.baz()`, where the
+/// property starts after the cursor or in a later line than the dot
+fn is_next_token_property(dot: Node, cursor_byte: usize) -> bool {
+    let property = match dot.parent() {
+        Some(postfix) if postfix.kind() == "member_expression" => {
+            postfix.child_by_field_name("property")
+        }
+        Some(postfix) if postfix.kind() == "nested_type_identifier" => {
+            postfix.child_by_field_name("name")
+        }
+        _ => None,
+    };
+    property.is_some_and(|property| {
+        property.start_byte() > cursor_byte
+            || property.start_position().row > dot.start_position().row
+    })
+}
+
+/// the dot is text of a comment or a literal rather than a token, e.g. `// see foo.log`,
+/// `'foo.log'` and `1.`
+fn is_dot_in_literal(root: Node, dot_byte: usize) -> bool {
+    root.descendant_for_byte_range(dot_byte, dot_byte + 1)
+        .is_some_and(|node| {
+            matches!(
+                node.kind(),
+                "comment"
+                    | "html_comment"
+                    | "string"
+                    | "string_fragment"
+                    | "template_string"
+                    | "regex"
+                    | "regex_pattern"
+                    | "number"
+            )
+        })
+}
+
+fn is_in_error(node: Node) -> bool {
+    let mut node = Some(node);
+    while let Some(current) = node {
+        if current.is_error() {
+            return true;
+        }
+        node = current.parent();
+    }
+    false
+}
+
+/// Parse a copy of `source` without the `.keyword` in `dot_byte..postfix_end_byte`, where the
+/// receiver is usually well formed. Bytes before the dot are the same in both trees, so the
+/// receivers found in it apply to the document, the postfix ends at `dot_byte` there
+fn reparse_without_postfix(
+    language: LanguageKind,
+    source: &str,
+    dot_byte: usize,
+    postfix_end_byte: usize,
+) -> Option<Tree> {
+    let mut virtual_source = String::with_capacity(source.len());
+    virtual_source.push_str(source.get(..dot_byte)?);
+    virtual_source.push_str(source.get(postfix_end_byte..)?);
+    let bytes = virtual_source.as_bytes();
+    language.parse_with(
+        &mut |byte_index, _| bytes.get(byte_index..).unwrap_or_default(),
+        None,
+    )
+}
+
+/// `receiver.keyword` or `receiver?.keyword` parsed as a `member_expression`, the receiver is
+/// its object, or `Receiver.keyword` in a type parsed as a `nested_type_identifier`, the
+/// receiver is its module
+fn postfix_member_expression(
+    root: Node,
+    dot_byte: usize,
+    cursor_byte: usize,
+) -> Option<(Node, Node)> {
+    // `?.` is a single token containing the dot
+    let dot = root.descendant_for_byte_range(dot_byte, dot_byte + 1)?;
+    if !matches!(dot.kind(), "." | "?." | "optional_chain")
+        || is_next_token_property(dot, cursor_byte)
+    {
+        return None;
+    }
+    let postfix = dot.parent()?;
+    let receiver = match postfix.kind() {
+        "member_expression" => postfix.child_by_field_name("object")?,
+        "nested_type_identifier" => postfix.child_by_field_name("module")?,
+        _ => return None,
+    };
+    if receiver.end_byte() > dot_byte {
+        return None;
+    }
+    // e.g. `let x: Foo<T>.promise` is parsed as `let` with an `ERROR` before the dot
+    let mut sibling = receiver.next_sibling();
+    while let Some(current) = sibling.filter(|current| *current != dot) {
+        if current.is_error() {
+            return None;
+        }
+        sibling = current.next_sibling();
+    }
+    Some((postfix, receiver))
+}
+
+/// the receiver, then every ancestor expression or type the postfix is the last operand of,
+/// e.g. `b`, `a + b` and `x = a + b` in `x = a + b.if`
+fn member_candidate_list<'tree>(
+    postfix: Node<'tree>,
+    receiver: Node<'tree>,
+) -> Vec<ReceiverCandidate<'tree>> {
+    let receiver_end = receiver.end_byte();
+    let mut candidate_list = vec![ReceiverCandidate {
+        node: receiver,
+        byte_range: receiver.byte_range(),
+    }];
+    let mut node = postfix;
+    while let Some(parent) = node.parent() {
+        if parent.end_byte() != postfix.end_byte() || !is_candidate_kind(parent) {
+            break;
+        }
+        if parent.start_byte() < receiver.start_byte() {
+            candidate_list.push(ReceiverCandidate {
+                node: parent,
+                byte_range: parent.start_byte()..receiver_end,
+            });
+        }
+        node = parent;
+    }
+    candidate_list
+}
+
+/// the keyword is not parsed as a property, e.g. `foo.` with an `ERROR` in it, use the node
+/// before the dot and the expressions ending with it
+fn fallback_candidate_list<'tree>(
+    root: Node<'tree>,
+    source: &[u8],
+    dot_byte: usize,
+) -> Vec<ReceiverCandidate<'tree>> {
+    let before_dot = source[..dot_byte.min(source.len())]
+        .iter()
+        .rposition(|byte| !byte.is_ascii_whitespace() && *byte != b'?');
+    let mut node =
+        match before_dot.and_then(|byte| root.named_descendant_for_byte_range(byte, byte)) {
+            Some(node) => node,
+            None => return vec![],
+        };
+    // `bar` of `foo.bar`, `()` of `foo()` and `<T>` of `Foo<T>` are not expressions or
+    // types on their own
+    while let Some(parent) = node.parent() {
+        let is_operand = ["property", "arguments", "type_arguments"]
+            .iter()
+            .all(|field| parent.child_by_field_name(field) != Some(node));
+        if is_operand || parent.end_byte() != node.end_byte() {
+            break;
+        }
+        node = parent;
+    }
+    // e.g. `foo.log` in jsx text
+    if !is_receiver_kind(node) || node.end_byte() > dot_byte {
+        return vec![];
+    }
+    let mut candidate_list = vec![ReceiverCandidate {
+        node,
+        byte_range: node.byte_range(),
+    }];
+    let end_byte = node.end_byte();
+    while let Some(parent) = node.parent() {
+        if node.is_error() || !is_candidate_kind(parent) || parent.end_byte() != end_byte {
+            break;
+        }
+        if parent.start_byte() < node.start_byte() {
+            candidate_list.push(ReceiverCandidate {
+                node: parent,
+                byte_range: parent.byte_range(),
+            });
+        }
+        node = parent;
+    }
+    candidate_list
+}
+
+/// an expression or a type a postfix applies to
+fn is_candidate_kind(node: Node) -> bool {
+    let kind = node.kind();
+    let is_expression = kind.ends_with("expression")
+        && !matches!(
+            kind,
+            "parenthesized_expression" | "jsx_expression" | "sequence_expression"
+        );
+    is_expression || is_type(node)
+}
+
+/// a candidate kind or an operand which is not an expression of its own kind, e.g. `foo`,
+/// `1` and `<A />`
+fn is_receiver_kind(node: Node) -> bool {
+    is_candidate_kind(node)
+        || matches!(
+            node.kind(),
+            "identifier"
+                | "this"
+                | "super"
+                | "number"
+                | "string"
+                | "template_string"
+                | "regex"
+                | "true"
+                | "false"
+                | "null"
+                | "undefined"
+                | "array"
+                | "object"
+                | "parenthesized_expression"
+                | "jsx_element"
+                | "jsx_self_closing_element"
+        )
+}
+
+fn is_type(node: Node) -> bool {
+    let kind = node.kind();
+    kind.ends_with("_type") || matches!(kind, "type_identifier" | "nested_type_identifier")
+}
+
+/// The statement, the jsx siblings and the function around a receiver, for templates editing
+/// beyond the receiver, e.g. `.try`, `.frag` and `.await`. Bytes are of the document
+#[derive(Debug, Clone, Default)]
+pub struct EnclosingScope {
+    /// the statement directly in a block, a `switch` case or the program
+    pub statement: Option<EnclosingRange>,
+    /// the jsx element receiver with the elements and texts before it in the same parent,
+    /// e.g. `<A />` and `<B />` of `<div><A /><B />.frag</div>`
+    pub jsx_sibling: Option<EnclosingRange>,
+    /// where `async ` goes to make the enclosing function async, `None` if it is already or
+    /// there is no function
+    pub async_insert_byte: Option<usize>,
+}
+
+/// A range a template wraps, see `TemplateTarget`
+#[derive(Debug, Clone)]
+pub struct EnclosingRange {
+    pub byte_range: Range<usize>,
+    /// starts of the lines after the first, except the blank ones and the ones in a template
+    /// string
+    pub line_start_list: Vec<usize>,
+}
+
+impl EnclosingScope {
+    /// `shift` is the length of the postfix removed from the tree after `dot_byte`, see
+    /// `reparse_without_postfix`, 0 for the tree of the document
+    pub fn new(receiver: Node, source: &[u8], dot_byte: usize, shift: usize) -> Self {
+        let to_document = |byte: usize| if byte < dot_byte { byte } else { byte + shift };
+        let to_tree = |byte: usize| {
+            if byte < dot_byte {
+                byte
+            } else {
+                byte.saturating_sub(shift).max(dot_byte)
+            }
+        };
+        let mut root = receiver;
+        while let Some(parent) = root.parent() {
+            root = parent;
+        }
+        let enclosing_range = |byte_range: Range<usize>| EnclosingRange {
+            line_start_list: (byte_range.start..byte_range.end.min(source.len()))
+                .filter(|byte| source[*byte] == b'\n')
+                .map(|byte| byte + 1)
+                .filter(|line_start| {
+                    let blank = source[*line_start..]
+                        .iter()
+                        .take_while(|byte| **byte != b'\n')
+                        .all(|byte| byte.is_ascii_whitespace());
+                    !blank && !is_in_template_string(root, to_tree(*line_start))
+                })
+                .collect(),
+            byte_range,
+        };
+        let mut scope = EnclosingScope::default();
+        let mut node = receiver;
+        while let Some(parent) = node.parent() {
+            // the expression body of an arrow function is not a statement
+            if parent.kind() == "arrow_function" && parent.child_by_field_name("body") == Some(node)
+            {
+                break;
+            }
+            if matches!(
+                parent.kind(),
+                "program" | "statement_block" | "switch_case" | "switch_default"
+            ) {
+                scope.statement = Some(enclosing_range(
+                    to_document(node.start_byte())..to_document(node.end_byte()),
+                ));
+                break;
+            }
+            node = parent;
+        }
+        if matches!(receiver.kind(), "jsx_element" | "jsx_self_closing_element") {
+            let mut start_byte = receiver.start_byte();
+            let mut sibling = receiver.prev_sibling().filter(
+                |_| matches!(receiver.parent(), Some(parent) if parent.kind() == "jsx_element"),
+            );
+            while let Some(current) = sibling {
+                match current.kind() {
+                    "jsx_element" | "jsx_self_closing_element" | "jsx_expression" => {
+                        start_byte = current.start_byte()
+                    }
+                    // from the first non-whitespace character of a text
+                    "jsx_text" => {
+                        if let Some(offset) = source[current.byte_range()]
+                            .iter()
+                            .position(|byte| !byte.is_ascii_whitespace())
+                        {
+                            start_byte = current.start_byte() + offset;
+                        }
+                    }
+                    _ => break,
+                }
+                sibling = current.prev_sibling();
+            }
+            scope.jsx_sibling = Some(enclosing_range(start_byte..receiver.end_byte()));
+        }
+        let mut node = Some(receiver);
+        while let Some(current) = node {
+            if matches!(
+                current.kind(),
+                "function_declaration"
+                    | "function_expression"
+                    | "function"
+                    | "generator_function_declaration"
+                    | "generator_function"
+                    | "arrow_function"
+                    | "method_definition"
+            ) {
+                let mut cursor = current.walk();
+                let child_list = current.children(&mut cursor).collect::<Vec<_>>();
+                // getters and setters can't be async
+                if child_list
+                    .iter()
+                    .any(|child| matches!(child.kind(), "async" | "get" | "set"))
+                {
+                    break;
+                }
+                // `async` of a method goes after `static` and before `*`
+                let insert_node = if current.kind() == "method_definition" {
+                    child_list.iter().copied().find(|child| {
+                        child.kind() == "*" || Some(*child) == current.child_by_field_name("name")
+                    })
+                } else {
+                    Some(current)
+                };
+                scope.async_insert_byte =
+                    insert_node.map(|insert_node| to_document(insert_node.start_byte()));
+                break;
+            }
+            node = current.parent();
+        }
+        scope
+    }
+}
+
+fn is_in_template_string(root: Node, byte: usize) -> bool {
+    let mut node = root.descendant_for_byte_range(byte, byte);
+    while let Some(current) = node {
+        if current.kind() == "template_string" && current.start_byte() < byte {
+            return true;
+        }
+        node = current.parent();
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::template::builtin_template_list;
+
+    /// candidates of the postfix before the cursor `‸` in `code`
+    fn candidate_list(language: LanguageKind, code: &str) -> (String, Vec<PostfixCandidate>) {
+        let cursor_byte = code.find('‸').unwrap();
+        let source = code.replace('‸', "");
+        let dot_byte = source[..cursor_byte].rfind('.').unwrap();
+        let bytes = source.as_bytes();
+        let tree = language
+            .parse_with(
+                &mut |byte_index, _| bytes.get(byte_index..).unwrap_or_default(),
+                None,
+            )
+            .unwrap();
+        let candidate_list =
+            postfix_candidate_list(language, tree.root_node(), &source, dot_byte, cursor_byte);
+        (source, candidate_list)
+    }
+
+    /// `(text, context)` of every candidate
+    fn summary(language: LanguageKind, code: &str) -> Vec<(String, PostfixContext)> {
+        let (source, candidate_list) = candidate_list(language, code);
+        candidate_list
+            .into_iter()
+            .map(|candidate| (source[candidate.byte_range].to_string(), candidate.context))
+            .collect()
+    }
+
+    #[test]
+    fn mid_edit_postfix_followed_by_statement() {
+        for code in [
+            "foo.bar.‸\nbaz();",
+            "foo.bar.lo‸\nbaz();",
+            "foo.bar.‸\nconst x = 1;",
+            "foo.bar.lo‸\nconst x = 1;",
+        ] {
+            assert_eq!(
+                summary(LanguageKind::TypeScript, code),
+                vec![("foo.bar".to_string(), PostfixContext::Statement)],
+                "{}",
+                code
+            );
+        }
+    }
+
+    fn candidate(text: &str, context: PostfixContext) -> (String, PostfixContext) {
+        (text.to_string(), context)
+    }
+
+    #[test]
+    fn every_expression_ending_with_the_receiver() {
+        assert_eq!(
+            summary(LanguageKind::TypeScript, "x = a + b.if‸"),
+            vec![
+                candidate("b", PostfixContext::Expression),
+                candidate("a + b", PostfixContext::Expression),
+                candidate("x = a + b", PostfixContext::Statement),
+            ]
+        );
+        assert_eq!(
+            summary(LanguageKind::TypeScript, "await foo().then‸"),
+            vec![
+                candidate("foo()", PostfixContext::Expression),
+                candidate("await foo()", PostfixContext::Statement),
+            ]
+        );
+    }
+
+    #[test]
+    fn optional_chain_receiver() {
+        assert_eq!(
+            summary(LanguageKind::TypeScript, "a?.b.log‸"),
+            vec![candidate("a?.b", PostfixContext::Statement)]
+        );
+        assert_eq!(
+            summary(LanguageKind::TypeScript, "a?.log‸"),
+            vec![candidate("a", PostfixContext::Statement)]
+        );
+    }
+
+    #[test]
+    fn multiline_chain_receiver() {
+        let (source, candidate_list) =
+            candidate_list(LanguageKind::TypeScript, "foo\n  .bar()\n  .baz.if‸");
+        assert_eq!(candidate_list.len(), 1);
+        assert_eq!(
+            &source[candidate_list[0].byte_range.clone()],
+            "foo\n  .bar()\n  .baz"
+        );
+        assert_eq!(candidate_list[0].preview, "foo…");
+        assert_eq!(candidate_list[0].context, PostfixContext::Statement);
+    }
+
+    #[test]
+    fn receiver_in_error_is_recovered() {
+        assert_eq!(
+            summary(LanguageKind::TypeScript, "foo(a).‸"),
+            vec![candidate("foo(a)", PostfixContext::Statement)]
+        );
+        assert_eq!(
+            summary(
+                LanguageKind::TypeScript,
+                "let y = a + foo.bar.‸\nconst z = 1"
+            ),
+            vec![
+                candidate("foo.bar", PostfixContext::Expression),
+                candidate("a + foo.bar", PostfixContext::Expression),
+            ]
+        );
+    }
+
+    #[test]
+    fn no_receiver_in_comment_or_literal() {
+        let table = [
+            (LanguageKind::TypeScript, "// see foo.log‸"),
+            (LanguageKind::TypeScript, "/* see foo.log‸ */"),
+            (LanguageKind::TypeScript, "let a = 'see foo.log‸'"),
+            (LanguageKind::TypeScript, "let a = \"see foo.‸"),
+            (LanguageKind::TypeScript, "let a = `see foo.log‸`"),
+            (LanguageKind::TypeScript, "let a = 1.‸"),
+            (LanguageKind::TypeScriptReact, "<div>see foo.log‸</div>"),
+            (LanguageKind::TypeScriptReact, "<div>\n  see foo.‸\n</div>"),
+        ];
+        for (language, code) in table {
+            assert_eq!(summary(language, code), vec![], "{}", code);
+        }
+        assert_eq!(
+            summary(LanguageKind::TypeScript, "let a = `${foo.log‸}`"),
+            vec![candidate("foo", PostfixContext::Expression)]
+        );
+        assert_eq!(
+            summary(LanguageKind::TypeScript, "'foo'.log‸"),
+            vec![candidate("'foo'", PostfixContext::Statement)]
+        );
+    }
+
+    #[test]
+    fn type_receiver() {
+        assert_eq!(
+            summary(LanguageKind::TypeScript, "let x: Foo<T>.promise‸;"),
+            vec![candidate("Foo<T>", PostfixContext::Type)]
+        );
+        assert_eq!(
+            summary(LanguageKind::TypeScript, "let x: A | B.opt‸;"),
+            vec![
+                candidate("B", PostfixContext::Type),
+                candidate("A | B", PostfixContext::Type),
+            ]
+        );
+        assert_eq!(
+            summary(LanguageKind::TypeScript, "let x: User.arr‸ = []"),
+            vec![candidate("User", PostfixContext::Type)]
+        );
+    }
+
+    #[test]
+    fn union_receiver_parenthesized_as_operand() {
+        let (source, candidate_list) =
+            candidate_list(LanguageKind::TypeScript, "let x: A | B.arr‸;");
+        let union = candidate_list.last().unwrap();
+        let receiver = ReceiverText::new(&source, union.kind, union.byte_range.clone());
+        let template = |key: &str| {
+            builtin_template_list()
+                .iter()
+                .find(|template| template.snippet_key == key)
+                .unwrap()
+        };
+        assert_eq!(receiver.for_template(template("arr")), "(A | B)");
+        assert_eq!(receiver.for_template(template("keyof")), "(A | B)");
+        assert_eq!(receiver.for_template(template("promise")), "A | B");
+        assert_eq!(receiver.for_template(template("record")), "A | B");
+        let user = ReceiverText::new("User", "type_identifier", 0..4);
+        assert_eq!(user.for_template(template("arr")), "User");
+    }
+
+    #[test]
+    fn jsx_receiver() {
+        assert_eq!(
+            summary(LanguageKind::TypeScriptReact, "<ul>{items.map‸}</ul>"),
+            vec![candidate("items", PostfixContext::JsxChild)]
+        );
+        assert_eq!(
+            summary(
+                LanguageKind::TypeScriptReact,
+                "<ul>{a ? items.map‸ : null}</ul>"
+            ),
+            vec![candidate("items", PostfixContext::JsxExpression)]
+        );
+        assert_eq!(
+            summary(LanguageKind::TypeScriptReact, "<ul>\n  <li />.frag‸\n</ul>"),
+            vec![candidate("<li />", PostfixContext::JsxText)]
+        );
+    }
+
+    #[test]
+    fn preview_is_shortened() {
+        let (_, candidate_list) = candidate_list(
+            LanguageKind::TypeScript,
+            "aVeryLongFunctionName(anArgument, anotherOne).if‸",
+        );
+        assert_eq!(
+            candidate_list[0].preview,
+            "aVeryLongFunctionName(anArgument…"
+        );
+    }
+
+    #[test]
+    fn enclosing_statement_and_function() {
+        let (source, candidate_list) = candidate_list(
+            LanguageKind::TypeScript,
+            "class A {\n  static *m() {\n    const x = foo(1,\n      2).await‸;\n  }\n}",
+        );
+        let scope = &candidate_list[0].scope;
+        let statement = scope.statement.as_ref().unwrap();
+        assert_eq!(
+            &source[statement.byte_range.clone()],
+            "const x = foo(1,\n      2).await;"
+        );
+        assert_eq!(statement.line_start_list.len(), 1);
+        assert_eq!(&source[scope.async_insert_byte.unwrap()..][..1], "*");
+    }
+
+    #[test]
+    fn statement_in_arrow_function() {
+        let (_, expression_body) =
+            candidate_list(LanguageKind::TypeScript, "list.map(x => foo(x).try‸)");
+        assert!(expression_body[0].scope.statement.is_none());
+        let (source, candidate_list) = candidate_list(
+            LanguageKind::TypeScript,
+            "list.map(x => {\n  foo(x).try‸\n})",
+        );
+        let statement = candidate_list[0].scope.statement.as_ref().unwrap();
+        assert_eq!(&source[statement.byte_range.clone()], "foo(x).try");
+    }
+}
@@ -0,0 +1,944 @@
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use inflector::Inflector;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::indent::{reindent, space_unit, IndentStyle};
+use crate::{language::LanguageKind, postfix_context::PostfixContext, type_query::TypeRequirement};
+
+/// built-in templates in the format of `tjs-postfix.templateMapList`
+const BUILTIN_TEMPLATE_JSON: &str = include_str!("builtin_template.json");
+
+/// template files looked up in the root of every workspace folder, the first one found is used
+pub const WORKSPACE_TEMPLATE_FILE_NAME_LIST: [&str; 2] = [".tjs-postfix.json", "tjs-postfix.toml"];
+
+/// A template of `tjs-postfix.templateMapList`, `code` is a snippet where
+/// - `$$` or `${expr}` is the receiver expression, `${expr:camelCase}` and
+///   `${expr:PascalCase}` transform it
+/// - `${name}`, `${name:default}` and `${name|a,b,c|}` are tab stops, numbered after the
+///   numeric ones in the order of first appearance, the same name is the same tab stop
+/// - the rest is the snippet syntax of lsp, e.g. `$0`, `${1:default}`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PostfixTemplate {
+    pub snippet_key: String,
+    pub code: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    /// language ids, offered in every language if not specified
+    #[serde(default)]
+    pub languages: Option<Vec<String>>,
+    /// a regex matched against the tree-sitter kind of receiver, e.g. `identifier`
+    #[serde(default)]
+    pub when: Option<String>,
+    /// offered everywhere if not specified
+    #[serde(default)]
+    pub context_list: Option<Vec<PostfixContext>>,
+    /// hidden or preselected by the type of receiver if the type is known
+    #[serde(default)]
+    pub type_requirement: Option<TypeRequirement>,
+    /// names the code uses, imported along with the completion if they are not yet
+    #[serde(default)]
+    pub requires_import: Option<Vec<RequiredImport>>,
+    /// what `${expr}` stands for, the receiver if not specified
+    #[serde(default)]
+    pub target: Option<TemplateTarget>,
+    /// make the function containing the receiver `async`, e.g. for `await ${expr}`
+    #[serde(default)]
+    pub mark_async: Option<bool>,
+}
+
+/// What a template wraps
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum TemplateTarget {
+    /// the receiver is replaced by the template
+    #[default]
+    Expression,
+    /// the statement containing the receiver is wrapped by the template, which must have
+    /// exactly one `${expr}`, e.g. `try {\n\t${expr}\n} catch (e) {}`
+    Statement,
+    /// the jsx element receiver and the elements before it in the same parent are wrapped,
+    /// like `statement`, e.g. `<>${expr}</>`
+    JsxSiblings,
+}
+
+/// A named import, e.g. `{ "name": "useState", "from": "react" }`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RequiredImport {
+    pub name: String,
+    pub from: String,
+}
+
+/// `PostfixTemplate` validated and parsed
+#[derive(Debug, Clone)]
+pub struct CompiledTemplate {
+    pub snippet_key: String,
+    pub code: String,
+    pub description: Option<String>,
+    pub language_list: Option<Vec<LanguageKind>>,
+    pub when: Option<Regex>,
+    pub context_list: Option<Vec<PostfixContext>>,
+    pub type_requirement: Option<TypeRequirement>,
+    pub required_import_list: Vec<RequiredImport>,
+    pub target: TemplateTarget,
+    pub mark_async: bool,
+    /// `${expr}` is an operand, e.g. of `${expr}[]` and `keyof ${expr}`, where a union type
+    /// receiver needs parentheses
+    pub expr_is_operand: bool,
+    segment_list: Vec<Segment>,
+    /// leading spaces of a level of indentation in `code`
+    space_unit: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Transform {
+    CamelCase,
+    PascalCase,
+}
+
+#[derive(Debug, Clone)]
+enum Segment {
+    /// already in the snippet syntax
+    Text(String),
+    Expr(Option<Transform>),
+}
+
+/// a segment before named tab stops are numbered
+enum RawSegment {
+    Text(String),
+    Expr(Option<Transform>),
+    Named {
+        name: String,
+        body: NamedBody,
+    },
+    /// a numeric tab stop, which may have named ones in its default
+    Group(Vec<RawSegment>),
+}
+
+enum NamedBody {
+    Plain,
+    Default(Vec<RawSegment>),
+    Choice(String),
+}
+
+impl CompiledTemplate {
+    pub fn compile(template: &PostfixTemplate) -> Result<Self, String> {
+        if template.snippet_key.is_empty() {
+            return Err("`snippetKey` is empty".to_string());
+        }
+        let language_list = match &template.languages {
+            Some(language_id_list) => Some(
+                language_id_list
+                    .iter()
+                    .map(|language_id| {
+                        LanguageKind::from_language_id(language_id)
+                            .ok_or_else(|| format!("unknown language `{}`", language_id))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?,
+            ),
+            None => None,
+        };
+        let when = match &template.when {
+            Some(pattern) => {
+                Some(Regex::new(pattern).map_err(|err| format!("invalid `when` regex: {}", err))?)
+            }
+            None => None,
+        };
+        let mut parser = TemplateParser {
+            chars: template.code.chars().collect(),
+            index: 0,
+            max_tab_stop: 0,
+        };
+        let raw_segment_list = parser.parse(None)?;
+        let mut name_list = vec![];
+        let mut segment_list = vec![];
+        number_named_tab_stop(
+            raw_segment_list,
+            parser.max_tab_stop,
+            &mut name_list,
+            &mut segment_list,
+        );
+        let target = template.target.unwrap_or_default();
+        if target != TemplateTarget::Expression {
+            let expr_list = segment_list
+                .iter()
+                .filter_map(|segment| match segment {
+                    Segment::Expr(transform) => Some(transform),
+                    Segment::Text(_) => None,
+                })
+                .collect::<Vec<_>>();
+            if expr_list != [&None] {
+                return Err(
+                    "a `statement` or `jsxSiblings` template must have exactly one `${expr}` without transform"
+                        .to_string(),
+                );
+            }
+        }
+        Ok(Self {
+            snippet_key: template.snippet_key.clone(),
+            code: template.code.clone(),
+            description: template.description.clone(),
+            language_list,
+            when,
+            context_list: template.context_list.clone(),
+            type_requirement: template.type_requirement,
+            required_import_list: template.requires_import.clone().unwrap_or_default(),
+            target,
+            mark_async: template.mark_async.unwrap_or(false),
+            expr_is_operand: is_expr_operand(&segment_list),
+            segment_list,
+            space_unit: space_unit(&template.code),
+        })
+    }
+
+    pub fn is_applicable(
+        &self,
+        language: LanguageKind,
+        context: PostfixContext,
+        receiver_kind: &str,
+    ) -> bool {
+        self.language_list
+            .as_ref()
+            .is_none_or(|language_list| language_list.contains(&language))
+            && self.context_list.as_ref().is_none_or(|context_list| {
+                context_list
+                    .iter()
+                    .any(|template_context| context.matches(*template_context))
+            })
+            && self
+                .when
+                .as_ref()
+                .is_none_or(|when| when.is_match(receiver_kind))
+    }
+
+    /// the snippet with the receiver expression filled, lines of the template are reindented
+    /// by `indent_style` after `base_indent`, while the receiver is kept as is
+    pub fn expand(&self, receiver: &str, base_indent: &str, indent_style: IndentStyle) -> String {
+        let mut snippet = String::new();
+        for segment in self.segment_list.iter() {
+            match segment {
+                Segment::Text(text) => {
+                    snippet.push_str(&reindent(text, self.space_unit, base_indent, indent_style))
+                }
+                Segment::Expr(transform) => {
+                    let expr = match transform {
+                        Some(Transform::CamelCase) => receiver.to_camel_case(),
+                        Some(Transform::PascalCase) => receiver.to_pascal_case(),
+                        None => receiver.to_string(),
+                    };
+                    snippet.push_str(&escape_snippet_text(&expr));
+                }
+            }
+        }
+        snippet
+    }
+
+    /// the template with the names in its code replaced, e.g. `useState` by
+    /// `React.useState`, the receiver filled later is kept as is
+    pub fn renamed(&self, rename_list: &[(String, String)]) -> Cow<'_, Self> {
+        if rename_list.is_empty() {
+            return Cow::Borrowed(self);
+        }
+        let rename = |text: &str| {
+            rename_list
+                .iter()
+                .fold(text.to_string(), |text, (name, new_name)| {
+                    replace_identifier(&text, name, new_name)
+                })
+        };
+        let mut template = self.clone();
+        for segment in template.segment_list.iter_mut() {
+            if let Segment::Text(text) = segment {
+                *text = rename(text);
+            }
+        }
+        template.code = rename(&template.code);
+        template.description = template.description.as_deref().map(rename);
+        Cow::Owned(template)
+    }
+
+    /// the snippets before and after `${expr}` of a wrapping template, reindented like
+    /// `expand`
+    pub fn expand_around(&self, base_indent: &str, indent_style: IndentStyle) -> (String, String) {
+        let mut prefix = String::new();
+        let mut suffix = String::new();
+        let mut current = &mut prefix;
+        for segment in self.segment_list.iter() {
+            match segment {
+                Segment::Text(text) => {
+                    current.push_str(&reindent(text, self.space_unit, base_indent, indent_style))
+                }
+                Segment::Expr(_) => current = &mut suffix,
+            }
+        }
+        (prefix, suffix)
+    }
+}
+
+/// replace `name` in `text` where it is a whole identifier and not a property, e.g. not
+/// `useStateful` or `React.useState`
+fn replace_identifier(text: &str, name: &str, new_name: &str) -> String {
+    let is_identifier_char = |c: char| c.is_alphanumeric() || matches!(c, '_' | '$');
+    let mut replaced = String::with_capacity(text.len());
+    let mut last_end = 0;
+    for (start, _) in text.match_indices(name) {
+        let end = start + name.len();
+        let before = text[..start].chars().next_back();
+        let after = text[end..].chars().next();
+        if before.is_some_and(|c| is_identifier_char(c) || c == '.')
+            || after.is_some_and(is_identifier_char)
+        {
+            continue;
+        }
+        replaced.push_str(&text[last_end..start]);
+        replaced.push_str(new_name);
+        last_end = end;
+    }
+    replaced.push_str(&text[last_end..]);
+    replaced
+}
+
+/// whether any `${expr}` is followed by `[`, `|` or `&`, or preceded by `keyof`, `readonly`,
+/// `|` or `&`
+fn is_expr_operand(segment_list: &[Segment]) -> bool {
+    fn text(segment: Option<&Segment>) -> &str {
+        match segment {
+            Some(Segment::Text(text)) => text.as_str(),
+            _ => "",
+        }
+    }
+    segment_list.iter().enumerate().any(|(i, segment)| {
+        if !matches!(segment, Segment::Expr(None)) {
+            return false;
+        }
+        let before = text(i.checked_sub(1).and_then(|i| segment_list.get(i))).trim_end();
+        let after = text(segment_list.get(i + 1)).trim_start();
+        after.starts_with(['[', '|', '&'])
+            || before.ends_with(['|', '&'])
+            || ["keyof", "readonly"].iter().any(|keyword| {
+                before
+                    .strip_suffix(keyword)
+                    .is_some_and(|rest| !rest.ends_with(|c: char| c.is_alphanumeric() || c == '_'))
+            })
+    })
+}
+
+/// compiled on first use
+pub fn builtin_template_list() -> &'static [CompiledTemplate] {
+    static BUILTIN_TEMPLATE_LIST: OnceLock<Vec<CompiledTemplate>> = OnceLock::new();
+    BUILTIN_TEMPLATE_LIST.get_or_init(|| {
+        serde_json::from_str::<Vec<PostfixTemplate>>(BUILTIN_TEMPLATE_JSON)
+            .unwrap()
+            .iter()
+            .map(|template| CompiledTemplate::compile(template).unwrap())
+            .collect()
+    })
+}
+
+/// templates failed to deserialize or compile are skipped and reported with their index in
+/// `source`, e.g. `templateMapList[0]`
+pub fn compile_template_list(
+    value: serde_json::Value,
+    source: &str,
+) -> (Vec<CompiledTemplate>, Vec<String>) {
+    let mut template_list = vec![];
+    let mut error_list = vec![];
+    let value_list = match serde_json::from_value::<Vec<serde_json::Value>>(value) {
+        Ok(value_list) => value_list,
+        Err(err) => {
+            return (
+                template_list,
+                vec![format!("{} expected an array: {}", source, err)],
+            )
+        }
+    };
+    for (i, value) in value_list.into_iter().enumerate() {
+        let compiled = serde_json::from_value::<PostfixTemplate>(value)
+            .map_err(|err| err.to_string())
+            .and_then(|template| {
+                CompiledTemplate::compile(&template)
+                    .map_err(|err| format!("`{}`: {}", template.snippet_key, err))
+            });
+        match compiled {
+            Ok(template) => template_list.push(template),
+            Err(err) => error_list.push(format!("{}[{}] {}", source, i, err)),
+        }
+    }
+    (template_list, error_list)
+}
+
+/// Templates of a workspace folder, which override user and built-in ones of the same key in
+/// the documents under `folder_path`
+#[derive(Debug, Clone)]
+pub struct WorkspaceTemplate {
+    pub folder_path: PathBuf,
+    pub file_path: PathBuf,
+    pub template_list: Vec<CompiledTemplate>,
+}
+
+/// `{ "templates": [...] }`, or `[[templates]]` tables in toml
+#[derive(Deserialize)]
+struct WorkspaceTemplateFile {
+    #[serde(default)]
+    templates: serde_json::Value,
+}
+
+/// read and compile the template file in the root of `folder_path`, `None` if there is no
+/// such file
+pub fn read_workspace_template(folder_path: &Path) -> Option<(WorkspaceTemplate, Vec<String>)> {
+    let file_path = WORKSPACE_TEMPLATE_FILE_NAME_LIST
+        .iter()
+        .map(|file_name| folder_path.join(file_name))
+        .find(|file_path| file_path.is_file())?;
+    let source = file_path.display().to_string();
+    let file = std::fs::read_to_string(&file_path)
+        .map_err(|err| err.to_string())
+        .and_then(|text| {
+            if file_path
+                .extension()
+                .is_some_and(|extension| extension == "toml")
+            {
+                toml::from_str::<WorkspaceTemplateFile>(&text).map_err(|err| err.to_string())
+            } else {
+                serde_json::from_str::<WorkspaceTemplateFile>(&text).map_err(|err| err.to_string())
+            }
+        });
+    let (template_list, error_list) = match file {
+        Ok(file) if file.templates.is_null() => (vec![], vec![]),
+        Ok(file) => compile_template_list(file.templates, &format!("{} templates", source)),
+        Err(err) => (vec![], vec![format!("{} {}", source, err)]),
+    };
+    Some((
+        WorkspaceTemplate {
+            folder_path: folder_path.to_path_buf(),
+            file_path,
+            template_list,
+        },
+        error_list,
+    ))
+}
+
+/// Where a template is defined, shown in the preview of completion items
+#[derive(Debug, Clone, Copy)]
+pub enum TemplateSource<'a> {
+    Builtin,
+    /// `tjs-postfix.templateMapList`
+    UserSetting,
+    WorkspaceFile(&'a Path),
+}
+
+impl std::fmt::Display for TemplateSource<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TemplateSource::Builtin => write!(f, "built-in template"),
+            TemplateSource::UserSetting => {
+                write!(f, "user template of `tjs-postfix.templateMapList`")
+            }
+            TemplateSource::WorkspaceFile(file_path) => {
+                write!(f, "workspace template of `{}`", file_path.display())
+            }
+        }
+    }
+}
+
+/// the text a snippet inserts with every tab stop left as its default or first choice
+pub fn snippet_to_text(snippet: &str) -> String {
+    let chars = snippet.chars().collect::<Vec<_>>();
+    let mut index = 0;
+    snippet_to_text_until(&chars, &mut index, None)
+}
+
+fn snippet_to_text_until(chars: &[char], index: &mut usize, terminator: Option<char>) -> String {
+    let mut text = String::new();
+    while let Some(&c) = chars.get(*index) {
+        if Some(c) == terminator {
+            break;
+        }
+        *index += 1;
+        match c {
+            '\\' => {
+                if let Some(&next) = chars.get(*index) {
+                    text.push(next);
+                    *index += 1;
+                }
+            }
+            '$' if chars.get(*index) == Some(&'{') => {
+                *index += 1;
+                while matches!(chars.get(*index), Some(c) if c.is_ascii_alphanumeric() || *c == '_')
+                {
+                    *index += 1;
+                }
+                match chars.get(*index) {
+                    Some(':') => {
+                        *index += 1;
+                        text.push_str(&snippet_to_text_until(chars, index, Some('}')));
+                    }
+                    Some('|') => {
+                        *index += 1;
+                        let choice = snippet_to_text_until(chars, index, Some('|'));
+                        text.push_str(choice.split(',').next().unwrap_or_default());
+                        *index += 1;
+                    }
+                    _ => {}
+                }
+                // the closing `}`
+                *index += 1;
+            }
+            '$' if matches!(chars.get(*index), Some(c) if c.is_ascii_alphanumeric() || *c == '_') => {
+                while matches!(chars.get(*index), Some(c) if c.is_ascii_alphanumeric() || *c == '_')
+                {
+                    *index += 1;
+                }
+            }
+            _ => text.push(c),
+        }
+    }
+    text
+}
+
+/// escape the characters meaningful in the snippet syntax
+pub fn escape_snippet_text(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '$' | '}') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn number_named_tab_stop(
+    raw_segment_list: Vec<RawSegment>,
+    max_tab_stop: usize,
+    name_list: &mut Vec<String>,
+    segment_list: &mut Vec<Segment>,
+) {
+    for raw_segment in raw_segment_list {
+        match raw_segment {
+            RawSegment::Text(text) => push_text(segment_list, text),
+            RawSegment::Expr(transform) => segment_list.push(Segment::Expr(transform)),
+            RawSegment::Group(group) => {
+                number_named_tab_stop(group, max_tab_stop, name_list, segment_list)
+            }
+            RawSegment::Named { name, body } => {
+                let index = match name_list.iter().position(|item| item == &name) {
+                    Some(position) => position,
+                    None => {
+                        name_list.push(name);
+                        name_list.len() - 1
+                    }
+                } + max_tab_stop
+                    + 1;
+                match body {
+                    NamedBody::Plain => push_text(segment_list, format!("${{{}}}", index)),
+                    NamedBody::Choice(choice) => {
+                        push_text(segment_list, format!("${{{}|{}|}}", index, choice))
+                    }
+                    NamedBody::Default(default) => {
+                        push_text(segment_list, format!("${{{}:", index));
+                        number_named_tab_stop(default, max_tab_stop, name_list, segment_list);
+                        push_text(segment_list, "}".to_string());
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn push_text(segment_list: &mut Vec<Segment>, text: String) {
+    match segment_list.last_mut() {
+        Some(Segment::Text(last)) => last.push_str(&text),
+        _ => segment_list.push(Segment::Text(text)),
+    }
+}
+
+struct TemplateParser {
+    chars: Vec<char>,
+    index: usize,
+    /// the largest numeric tab stop, named ones are numbered after it
+    max_tab_stop: usize,
+}
+
+impl TemplateParser {
+    /// parse until `terminator` or the end of template
+    fn parse(&mut self, terminator: Option<char>) -> Result<Vec<RawSegment>, String> {
+        let mut segment_list = vec![];
+        let mut text = String::new();
+        while let Some(&c) = self.chars.get(self.index) {
+            if Some(c) == terminator {
+                break;
+            }
+            self.index += 1;
+            match c {
+                '\\' => {
+                    text.push(c);
+                    if let Some(&next) = self.chars.get(self.index) {
+                        text.push(next);
+                        self.index += 1;
+                    }
+                }
+                '$' => match self.chars.get(self.index) {
+                    Some('$') => {
+                        self.index += 1;
+                        push_segment(&mut segment_list, &mut text, RawSegment::Expr(None));
+                    }
+                    Some('{') => {
+                        self.index += 1;
+                        let segment = self.parse_placeholder()?;
+                        push_segment(&mut segment_list, &mut text, segment);
+                    }
+                    Some(next) if next.is_ascii_digit() => {
+                        let tab_stop = self.take_while(|c| c.is_ascii_digit());
+                        self.max_tab_stop = self.max_tab_stop.max(tab_stop.parse().unwrap_or(0));
+                        text.push('$');
+                        text.push_str(&tab_stop);
+                    }
+                    _ => text.push(c),
+                },
+                _ => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            segment_list.push(RawSegment::Text(text));
+        }
+        Ok(segment_list)
+    }
+
+    /// after `${`, consume until the matching `}`
+    fn parse_placeholder(&mut self) -> Result<RawSegment, String> {
+        let start = self.index - 2;
+        let name = self.take_while(|c| c.is_ascii_alphanumeric() || c == '_');
+        if name.is_empty() {
+            return Err(format!("invalid placeholder at {}", start));
+        }
+        let body = match self.chars.get(self.index) {
+            Some('}') => NamedBody::Plain,
+            Some(':') => {
+                self.index += 1;
+                if name == "expr" {
+                    let transform = self.take_while(|c| c != '}');
+                    self.expect_close(start)?;
+                    return match transform.as_str() {
+                        "camelCase" => Ok(RawSegment::Expr(Some(Transform::CamelCase))),
+                        "PascalCase" => Ok(RawSegment::Expr(Some(Transform::PascalCase))),
+                        _ => Err(format!(
+                            "unknown transformation `{}`, expected `camelCase` or `PascalCase`",
+                            transform
+                        )),
+                    };
+                }
+                NamedBody::Default(self.parse(Some('}'))?)
+            }
+            Some('|') => {
+                self.index += 1;
+                let choice = self.take_while(|c| c != '|');
+                if self.chars.get(self.index) != Some(&'|') {
+                    return Err(format!("unterminated choice at {}", start));
+                }
+                self.index += 1;
+                NamedBody::Choice(choice)
+            }
+            None => return Err(format!("unterminated placeholder at {}", start)),
+            _ => return Err(format!("invalid placeholder at {}", start)),
+        };
+        self.expect_close(start)?;
+        if name == "expr" {
+            return match body {
+                NamedBody::Plain => Ok(RawSegment::Expr(None)),
+                _ => Err("`${expr}` can't have choices".to_string()),
+            };
+        }
+        // numeric tab stops are kept as is
+        if name.chars().all(|c| c.is_ascii_digit()) {
+            self.max_tab_stop = self.max_tab_stop.max(name.parse().unwrap_or(0));
+            let mut segment_list = vec![RawSegment::Text(format!("${{{}", name))];
+            match body {
+                NamedBody::Plain => {}
+                NamedBody::Choice(choice) => {
+                    segment_list.push(RawSegment::Text(format!("|{}|", choice)))
+                }
+                NamedBody::Default(default) => {
+                    segment_list.push(RawSegment::Text(":".to_string()));
+                    segment_list.extend(default);
+                }
+            }
+            segment_list.push(RawSegment::Text("}".to_string()));
+            return Ok(RawSegment::Group(segment_list));
+        }
+        Ok(RawSegment::Named { name, body })
+    }
+
+    fn expect_close(&mut self, start: usize) -> Result<(), String> {
+        if self.chars.get(self.index) == Some(&'}') {
+            self.index += 1;
+            Ok(())
+        } else {
+            Err(format!("unterminated placeholder at {}", start))
+        }
+    }
+
+    fn take_while(&mut self, predicate: impl Fn(char) -> bool) -> String {
+        let start = self.index;
+        while matches!(self.chars.get(self.index), Some(&c) if predicate(c)) {
+            self.index += 1;
+        }
+        self.chars[start..self.index].iter().collect()
+    }
+}
+
+fn push_segment(segment_list: &mut Vec<RawSegment>, text: &mut String, segment: RawSegment) {
+    if !text.is_empty() {
+        segment_list.push(RawSegment::Text(std::mem::take(text)));
+    }
+    segment_list.push(segment);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn template(code: &str) -> PostfixTemplate {
+        PostfixTemplate {
+            snippet_key: "key".to_string(),
+            code: code.to_string(),
+            description: None,
+            languages: None,
+            when: None,
+            context_list: None,
+            type_requirement: None,
+            requires_import: None,
+            target: None,
+            mark_async: None,
+        }
+    }
+
+    fn expand(code: &str, receiver: &str) -> String {
+        CompiledTemplate::compile(&template(code)).unwrap().expand(
+            receiver,
+            "",
+            IndentStyle::default(),
+        )
+    }
+
+    #[test]
+    fn expand_template() {
+        for (code, receiver, expanded) in [
+            ("!${expr}", "a", "!a"),
+            ("console.log($$)", "a", "console.log(a)"),
+            ("if (${expr}) {\n\t${0}\n}", "a", "if (a) {\n    ${0}\n}"),
+            (
+                "const [${expr}, set${expr:PascalCase}] = useState()",
+                "fooBar",
+                "const [fooBar, setFooBar] = useState()",
+            ),
+            ("const ${expr:camelCase} = 1", "FooBar", "const fooBar = 1"),
+            // named tab stops are numbered after the numeric ones
+            (
+                "${expr}.forEach(${item} => ${1:log}(${item}))",
+                "xs",
+                "xs.forEach(${2} => ${1:log}(${2}))",
+            ),
+            (
+                "${name:default} ${kind|let,const|} ${name}",
+                "a",
+                "${1:default} ${2|let,const|} ${1}",
+            ),
+            ("${1:${inner}} $3", "a", "${1:${4}} $3"),
+            ("${outer:${inner:x}}", "a", "${1:${2:x}}"),
+            // snippet syntax of the receiver is escaped, of the template is kept
+            ("log(${expr})", "a.$b}", "log(a.\\$b\\})"),
+            ("\\$${expr}", "a", "\\$a"),
+        ] {
+            assert_eq!(expand(code, receiver), expanded, "{}", code);
+        }
+    }
+
+    #[test]
+    fn reindent_template() {
+        let tab = IndentStyle {
+            insert_spaces: false,
+            tab_size: 4,
+        };
+        let two_spaces = IndentStyle {
+            insert_spaces: true,
+            tab_size: 2,
+        };
+        for (code, base_indent, style, expanded) in [
+            (
+                "if (${expr}) {\n\t${0}\n}",
+                "  ",
+                tab,
+                "if (a) {\n  \t${0}\n  }",
+            ),
+            (
+                "if (${expr}) {\n\t${0}\n}",
+                "",
+                two_spaces,
+                "if (a) {\n  ${0}\n}",
+            ),
+            // 2 spaces is a level of the template
+            (
+                "{\n  ${expr}\n    ${0}\n}",
+                "\t",
+                tab,
+                "{\n\t\ta\n\t\t\t${0}\n\t}",
+            ),
+        ] {
+            let template = CompiledTemplate::compile(&template(code)).unwrap();
+            assert_eq!(
+                template.expand("a", base_indent, style),
+                expanded,
+                "{}",
+                code
+            );
+        }
+        // lines of the receiver are kept as is
+        assert_eq!(expand("[\n\t${expr}\n]", "f(\n1)"), "[\n    f(\n1)\n]");
+    }
+
+    #[test]
+    fn expand_around_statement() {
+        let mut statement = template("try {\n\t${expr}\n} catch (${1:e}) {\n\t${0}\n}");
+        statement.target = Some(TemplateTarget::Statement);
+        let template = CompiledTemplate::compile(&statement).unwrap();
+        assert_eq!(
+            template.expand_around("  ", IndentStyle::default()),
+            (
+                "try {\n      ".to_string(),
+                "\n  } catch (${1:e}) {\n      ${0}\n  }".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn compile_error() {
+        for (code, error) in [
+            ("${}", "invalid placeholder at 0"),
+            ("a ${name", "unterminated placeholder at 2"),
+            ("${name?}", "invalid placeholder at 0"),
+            ("${kind|let,const}", "unterminated choice at 0"),
+            ("${expr:snake_case}", "unknown transformation `snake_case`"),
+            ("${expr|a,b|}", "`${expr}` can't have choices"),
+            ("${expr:camelCase", "unterminated placeholder at 0"),
+        ] {
+            let compiled = CompiledTemplate::compile(&template(code));
+            assert!(
+                compiled.as_ref().is_err_and(|err| err.starts_with(error)),
+                "{}: {:?}",
+                code,
+                compiled.err()
+            );
+        }
+        let mut empty_key = template("${expr}");
+        empty_key.snippet_key = String::new();
+        let mut unknown_language = template("${expr}");
+        unknown_language.languages = Some(vec!["rust".to_string()]);
+        let mut invalid_when = template("${expr}");
+        invalid_when.when = Some("(".to_string());
+        let mut statement = template("${expr} ${expr}");
+        statement.target = Some(TemplateTarget::Statement);
+        for (template, error) in [
+            (empty_key, "`snippetKey` is empty"),
+            (unknown_language, "unknown language `rust`"),
+            (invalid_when, "invalid `when` regex"),
+            (
+                statement,
+                "a `statement` or `jsxSiblings` template must have exactly one",
+            ),
+        ] {
+            let compiled = CompiledTemplate::compile(&template);
+            assert!(
+                compiled.as_ref().is_err_and(|err| err.starts_with(error)),
+                "{:?}",
+                compiled.err()
+            );
+        }
+    }
+
+    #[test]
+    fn snippet_to_plain_text() {
+        assert_eq!(
+            snippet_to_text("for (${1:item} of ${2|a,b|}) {\n\t$0\n} \\$1"),
+            "for (item of a) {\n\t\n} $1"
+        );
+    }
+
+    #[test]
+    fn builtin_templates_compile() {
+        assert!(!builtin_template_list().is_empty());
+    }
+
+    #[test]
+    fn rename_identifier() {
+        let table = [
+            ("useState(${0})", "React.useState(${0})"),
+            ("useStateful(useState)", "useStateful(React.useState)"),
+            ("React.useState", "React.useState"),
+            ("$useState", "$useState"),
+        ];
+        for (text, expected) in table {
+            assert_eq!(
+                replace_identifier(text, "useState", "React.useState"),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn expr_is_operand() {
+        let table = [
+            ("arr", true),
+            ("keyof", true),
+            ("opt", true),
+            ("promise", false),
+            ("partial", false),
+            ("record", false),
+        ];
+        for (key, expected) in table {
+            let template = builtin_template_list()
+                .iter()
+                .find(|template| template.snippet_key == key)
+                .unwrap();
+            assert_eq!(template.expr_is_operand, expected, "{}", key);
+        }
+        let table = [
+            ("readonly ${expr}[]", true),
+            ("A & ${expr}", true),
+            ("Array<${expr}>", false),
+            ("notkeyof ${expr}", false),
+            ("${expr:camelCase}[]", false),
+        ];
+        for (code, expected) in table {
+            let template = CompiledTemplate::compile(&template(code)).unwrap();
+            assert_eq!(template.expr_is_operand, expected, "{}", code);
+        }
+    }
+
+    #[test]
+    fn frag_only_among_jsx_children() {
+        let frag = builtin_template_list()
+            .iter()
+            .find(|template| template.snippet_key == "frag")
+            .unwrap();
+        let language = LanguageKind::TypeScriptReact;
+        assert!(frag.is_applicable(language, PostfixContext::JsxText, "jsx_element"));
+        assert!(frag.is_applicable(
+            language,
+            PostfixContext::JsxText,
+            "jsx_self_closing_element"
+        ));
+        assert!(!frag.is_applicable(language, PostfixContext::JsxText, "identifier"));
+        for context in [
+            PostfixContext::Expression,
+            PostfixContext::Statement,
+            PostfixContext::JsxExpression,
+        ] {
+            assert!(!frag.is_applicable(language, context, "jsx_element"));
+        }
+    }
+}
@@ -0,0 +1,429 @@
+use std::{
+    ops::Range,
+    process::Stdio,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    process::{Child, Command},
+    sync::{mpsc, oneshot},
+    time::Instant,
+};
+
+/// completion doesn't wait longer than this for the type of receiver
+const TYPE_QUERY_TIMEOUT: Duration = Duration::from_millis(300);
+
+/// One line of json written to the stdin of the type query process, the text is sent once
+/// by `update` and each query of the batch after it only carries offsets
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "method", rename_all = "camelCase")]
+pub enum TypeQueryMessage {
+    Update(TypeQueryUpdate),
+    Query(TypeQueryRequest),
+}
+
+/// The unsaved text of a document, which later queries of the file are answered on
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct TypeQueryUpdate {
+    pub file_name: String,
+    pub text: String,
+}
+
+/// Offsets are utf-16 code units as typescript counts them
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct TypeQueryRequest {
+    pub id: u64,
+    pub file_name: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// One line of json read from the stdout of the type query process
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct TypeQueryResponse {
+    pub id: u64,
+    pub type_info: Option<TypeInfo>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct TypeInfo {
+    /// the type printed by typescript, e.g. `string[]`
+    pub display: String,
+    #[serde(default)]
+    pub iterable: bool,
+    /// an array or a tuple, strings, `Set` and `Map` are iterable but not arrays
+    #[serde(default)]
+    pub array: bool,
+    #[serde(default)]
+    pub thenable: bool,
+    #[serde(default)]
+    pub boolean: bool,
+}
+
+/// What a template expects of the type of receiver
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum TypeRequirement {
+    Iterable,
+    Array,
+    Thenable,
+    Boolean,
+}
+
+impl TypeRequirement {
+    pub fn is_satisfied_by(&self, type_info: &TypeInfo) -> bool {
+        match self {
+            TypeRequirement::Iterable => type_info.iterable,
+            TypeRequirement::Array => type_info.array,
+            TypeRequirement::Thenable => type_info.thenable,
+            TypeRequirement::Boolean => type_info.boolean,
+        }
+    }
+
+    /// a template of strict requirement is hidden for other types, e.g. `for of` a number
+    /// never works, but `!value` is fine for any value
+    pub fn is_strict(&self) -> bool {
+        !matches!(self, TypeRequirement::Boolean)
+    }
+}
+
+type PendingQuery = (TypeQueryRequest, oneshot::Sender<Option<TypeInfo>>);
+
+/// the queries of `query_list` about one text
+type PendingBatch = (TypeQueryUpdate, Vec<PendingQuery>);
+
+/// Client of the type query process, e.g. `node ts-server/out/type_query.js`, which answers
+/// the type of an expression line by line over stdio. Queries are answered one by one
+pub struct TypeQueryClient {
+    sender: mpsc::UnboundedSender<PendingBatch>,
+    /// killed on drop
+    _child: Option<Child>,
+}
+
+impl TypeQueryClient {
+    /// spawn `command` with `args`, the process is killed once the client is dropped
+    pub fn spawn(command: &str, args: &[String]) -> std::io::Result<Self> {
+        let mut child = Command::new(command)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .kill_on_drop(true)
+            .spawn()?;
+        let stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+        Ok(Self {
+            _child: Some(child),
+            ..Self::from_io(stdout, stdin)
+        })
+    }
+
+    /// talk to the type query process over the given pipes
+    pub fn from_io<R, W>(reader: R, mut writer: W) -> Self
+    where
+        R: AsyncRead + Unpin + Send + 'static,
+        W: AsyncWrite + Unpin + Send + 'static,
+    {
+        let (sender, mut receiver) = mpsc::unbounded_channel::<PendingBatch>();
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(reader).lines();
+            while let Some((update, mut query_list)) = receiver.recv().await {
+                // the caller has given up waiting
+                query_list.retain(|(_, response_sender)| !response_sender.is_closed());
+                if query_list.is_empty() {
+                    continue;
+                }
+                if let Err(err) = write_message(&mut writer, TypeQueryMessage::Update(update)).await
+                {
+                    warn!("type query process is gone: {}", err);
+                    return;
+                }
+                for (request, response_sender) in query_list {
+                    if response_sender.is_closed() {
+                        continue;
+                    }
+                    let id = request.id;
+                    if let Err(err) =
+                        write_message(&mut writer, TypeQueryMessage::Query(request)).await
+                    {
+                        warn!("type query process is gone: {}", err);
+                        return;
+                    }
+                    // responses of the queries timed out before may still arrive, skip them
+                    let type_info = loop {
+                        match lines.next_line().await {
+                            Ok(Some(line)) => {
+                                match serde_json::from_str::<TypeQueryResponse>(&line) {
+                                    Ok(response) if response.id == id => break response.type_info,
+                                    Ok(_) => continue,
+                                    Err(err) => {
+                                        debug!("invalid type query response {}: {}", line, err)
+                                    }
+                                }
+                            }
+                            _ => {
+                                warn!("type query process is gone");
+                                return;
+                            }
+                        }
+                    };
+                    let _ = response_sender.send(type_info);
+                }
+            }
+        });
+        Self {
+            sender,
+            _child: None,
+        }
+    }
+
+    /// an in process stand-in of the type query process for tests, `responder` answers
+    /// every request with the text of the last update through the same line based protocol
+    pub fn stub<F>(responder: F) -> Self
+    where
+        F: Fn(&str, &TypeQueryRequest) -> Option<TypeInfo> + Send + 'static,
+    {
+        let (client_io, stub_io) = tokio::io::duplex(64 * 1024);
+        let (client_reader, client_writer) = tokio::io::split(client_io);
+        let (stub_reader, mut stub_writer) = tokio::io::split(stub_io);
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(stub_reader).lines();
+            let mut text = String::new();
+            while let Ok(Some(line)) = lines.next_line().await {
+                let request = match serde_json::from_str::<TypeQueryMessage>(&line) {
+                    Ok(TypeQueryMessage::Update(update)) => {
+                        text = update.text;
+                        continue;
+                    }
+                    Ok(TypeQueryMessage::Query(request)) => request,
+                    Err(_) => continue,
+                };
+                let response = TypeQueryResponse {
+                    id: request.id,
+                    type_info: responder(&text, &request),
+                };
+                let mut line = serde_json::to_string(&response).unwrap();
+                line.push('\n');
+                if stub_writer.write_all(line.as_bytes()).await.is_err() {
+                    return;
+                }
+            }
+        });
+        Self::from_io(client_reader, client_writer)
+    }
+
+    /// the type of the expression in each range of `offset_range_list`, `None` if it is unknown
+    /// in time. The text is sent once for the whole list, and all the queries share one
+    /// deadline, so the list waits at most one timeout
+    pub async fn query_list(
+        &self,
+        file_name: String,
+        text: String,
+        offset_range_list: &[Range<usize>],
+    ) -> Vec<Option<TypeInfo>> {
+        let deadline = Instant::now() + TYPE_QUERY_TIMEOUT;
+        let (query_list, response_receiver_list): (Vec<_>, Vec<_>) = offset_range_list
+            .iter()
+            .map(|range| {
+                let request = TypeQueryRequest {
+                    id: next_request_id(),
+                    file_name: file_name.clone(),
+                    start: range.start,
+                    end: range.end,
+                };
+                let (response_sender, response_receiver) = oneshot::channel();
+                ((request, response_sender), response_receiver)
+            })
+            .unzip();
+        let update = TypeQueryUpdate { file_name, text };
+        if self.sender.send((update, query_list)).is_err() {
+            return vec![None; offset_range_list.len()];
+        }
+        let mut type_info_list = Vec::with_capacity(response_receiver_list.len());
+        for response_receiver in response_receiver_list {
+            let type_info = tokio::time::timeout_at(deadline, response_receiver)
+                .await
+                .ok()
+                .and_then(|response| response.ok().flatten());
+            type_info_list.push(type_info);
+        }
+        type_info_list
+    }
+}
+
+async fn write_message<W>(writer: &mut W, message: TypeQueryMessage) -> std::io::Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    let mut line = serde_json::to_string(&message).unwrap();
+    line.push('\n');
+    writer.write_all(line.as_bytes()).await?;
+    writer.flush().await
+}
+
+fn next_request_id() -> u64 {
+    static REQUEST_ID: AtomicU64 = AtomicU64::new(0);
+    REQUEST_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// the json value of `tjs-postfix.typeQueryCommand`, e.g. `["node", "type_query.js"]`
+pub fn parse_type_query_command(value: serde_json::Value) -> Option<(String, Vec<String>)> {
+    let mut command = serde_json::from_value::<Vec<String>>(value)
+        .ok()?
+        .into_iter();
+    let program = command.next()?;
+    Some((program, command.collect()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn query_list_keeps_order() {
+        let client = TypeQueryClient::stub(|text, request| {
+            Some(TypeInfo {
+                display: text[request.start..request.end].to_string(),
+                ..TypeInfo::default()
+            })
+        });
+        let type_info_list = client
+            .query_list("a.ts".into(), "foo.bar".into(), &[0..3, 0..7, 4..7])
+            .await;
+        let display_list = type_info_list
+            .into_iter()
+            .map(|type_info| type_info.map(|type_info| type_info.display))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            display_list,
+            [
+                Some("foo".into()),
+                Some("foo.bar".into()),
+                Some("bar".into())
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn unknown_type() {
+        let client = TypeQueryClient::stub(|_, _| None);
+        let type_info_list = client
+            .query_list("a.ts".into(), "foo".into(), std::slice::from_ref(&(0..3)))
+            .await;
+        assert!(type_info_list[0].is_none());
+    }
+
+    #[tokio::test]
+    async fn query_list_waits_one_timeout() {
+        // a process which reads requests but never answers
+        let (client_io, _process_io) = tokio::io::duplex(64 * 1024);
+        let (client_reader, client_writer) = tokio::io::split(client_io);
+        let client = TypeQueryClient::from_io(client_reader, client_writer);
+        let start = Instant::now();
+        let type_info_list = client
+            .query_list("a.ts".into(), "foo.bar".into(), &[0..3, 0..7, 4..7])
+            .await;
+        let elapsed = start.elapsed();
+        assert!(type_info_list.iter().all(Option::is_none));
+        assert!(elapsed >= TYPE_QUERY_TIMEOUT);
+        assert!(elapsed < TYPE_QUERY_TIMEOUT * 2, "{:?}", elapsed);
+    }
+
+    #[tokio::test]
+    async fn answered_after_a_timed_out_query() {
+        let (client_io, process_io) = tokio::io::duplex(64 * 1024);
+        let (client_reader, client_writer) = tokio::io::split(client_io);
+        let client = TypeQueryClient::from_io(client_reader, client_writer);
+        let (process_reader, mut process_writer) = tokio::io::split(process_io);
+        let mut lines = BufReader::new(process_reader).lines();
+        let respond = |line: String, display: &str| {
+            let request = match serde_json::from_str::<TypeQueryMessage>(&line).unwrap() {
+                TypeQueryMessage::Query(request) => request,
+                message => panic!("{:?} is not a query", message),
+            };
+            let response = TypeQueryResponse {
+                id: request.id,
+                type_info: Some(TypeInfo {
+                    display: display.into(),
+                    ..TypeInfo::default()
+                }),
+            };
+            serde_json::to_string(&response).unwrap() + "\n"
+        };
+        let timed_out =
+            client.query_list("a.ts".into(), "foo".into(), std::slice::from_ref(&(0..3)));
+        let read_query = async {
+            lines.next_line().await.unwrap().unwrap();
+            lines.next_line().await.unwrap().unwrap()
+        };
+        let (type_info_list, line) = tokio::join!(timed_out, read_query);
+        assert!(type_info_list[0].is_none());
+        let late_response = respond(line, "foo");
+
+        let answered =
+            client.query_list("a.ts".into(), "bar".into(), std::slice::from_ref(&(0..3)));
+        let process = async {
+            process_writer
+                .write_all(late_response.as_bytes())
+                .await
+                .unwrap();
+            // the update of `bar`
+            lines.next_line().await.unwrap().unwrap();
+            let line = lines.next_line().await.unwrap().unwrap();
+            let response = respond(line, "bar");
+            process_writer.write_all(response.as_bytes()).await.unwrap();
+        };
+        let (type_info_list, _) = tokio::join!(answered, process);
+        assert_eq!(type_info_list[0].as_ref().unwrap().display, "bar");
+    }
+
+    #[tokio::test]
+    async fn text_sent_once_per_batch() {
+        let (client_io, process_io) = tokio::io::duplex(64 * 1024);
+        let (client_reader, client_writer) = tokio::io::split(client_io);
+        let client = TypeQueryClient::from_io(client_reader, client_writer);
+        let (process_reader, mut process_writer) = tokio::io::split(process_io);
+        let mut lines = BufReader::new(process_reader).lines();
+        let process = async {
+            let mut message_list = vec![];
+            for _ in 0..4 {
+                let line = lines.next_line().await.unwrap().unwrap();
+                let message = serde_json::from_str::<TypeQueryMessage>(&line).unwrap();
+                if let TypeQueryMessage::Query(request) = &message {
+                    let response = TypeQueryResponse {
+                        id: request.id,
+                        type_info: None,
+                    };
+                    let line = serde_json::to_string(&response).unwrap() + "\n";
+                    process_writer.write_all(line.as_bytes()).await.unwrap();
+                }
+                message_list.push(message);
+            }
+            message_list
+        };
+        let (_, message_list) = tokio::join!(
+            client.query_list("a.ts".into(), "foo.bar".into(), &[0..3, 0..7, 4..7]),
+            process
+        );
+        match &message_list[0] {
+            TypeQueryMessage::Update(update) => assert_eq!(update.text, "foo.bar"),
+            message => panic!("{:?} is not an update", message),
+        }
+        let range_list = message_list[1..]
+            .iter()
+            .map(|message| match message {
+                TypeQueryMessage::Query(request) => request.start..request.end,
+                message => panic!("{:?} is not a query", message),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(range_list, [0..3, 0..7, 4..7]);
+    }
+}
@@ -0,0 +1,202 @@
+/**
+ * Type query process of tjs-language-server, configured by `tjs-postfix.typeQueryCommand`, e.g.
+ * `["node", "<path>/ts-server/out/type_query.js"]`.
+ *
+ * Every line of stdin is a json message. `{ method: "update", fileName, text }` sets the unsaved
+ * text of a file and is not answered, `{ method: "query", id, fileName, start, end }` asks the
+ * type of the expression at the utf-16 offsets `start` and `end` of the last text of the file,
+ * and is answered by a line `{ id, typeInfo }` on stdout.
+ */
+import * as path from "path";
+import * as readline from "readline";
+import * as ts from "typescript";
+
+interface TypeQueryUpdate {
+  method: "update";
+  fileName: string;
+  text: string;
+}
+
+interface TypeQueryRequest {
+  method: "query";
+  id: number;
+  fileName: string;
+  start: number;
+  end: number;
+}
+
+type TypeQueryMessage = TypeQueryUpdate | TypeQueryRequest;
+
+interface TypeInfo {
+  display: string;
+  iterable: boolean;
+  array: boolean;
+  thenable: boolean;
+  boolean: boolean;
+}
+
+// stdout is the channel of responses
+console.log = console.error;
+
+const defaultCompilerOptions: ts.CompilerOptions = {
+  allowJs: true,
+  checkJs: true,
+  jsx: ts.JsxEmit.Preserve,
+  target: ts.ScriptTarget.ESNext,
+  module: ts.ModuleKind.ESNext,
+  moduleResolution: ts.ModuleResolutionKind.NodeJs,
+  strict: true,
+};
+
+const openFiles: Map<string, { version: number; text: string }> = new Map();
+let compilerOptions: ts.CompilerOptions | undefined;
+
+const servicesHost: ts.LanguageServiceHost = {
+  getScriptFileNames: () => [...openFiles.keys()],
+  getScriptVersion: fileName => openFiles.get(fileName)?.version.toString() ?? "0",
+  getScriptSnapshot: fileName => {
+    const file = openFiles.get(fileName);
+    if (file) {
+      return ts.ScriptSnapshot.fromString(file.text);
+    }
+    const text = ts.sys.readFile(fileName);
+    return text === undefined ? undefined : ts.ScriptSnapshot.fromString(text);
+  },
+  getCurrentDirectory: () => process.cwd(),
+  getCompilationSettings: () => compilerOptions ?? defaultCompilerOptions,
+  getDefaultLibFileName: options => ts.getDefaultLibFilePath(options),
+  fileExists: ts.sys.fileExists,
+  readFile: ts.sys.readFile,
+  readDirectory: ts.sys.readDirectory,
+  directoryExists: ts.sys.directoryExists,
+  getDirectories: ts.sys.getDirectories,
+};
+
+const languageService = ts.createLanguageService(servicesHost, ts.createDocumentRegistry());
+
+/** use the `tsconfig.json` of the first queried file for the whole process */
+function loadCompilerOptions(fileName: string): ts.CompilerOptions {
+  const configPath = ts.findConfigFile(path.dirname(fileName), ts.sys.fileExists);
+  if (!configPath) {
+    return defaultCompilerOptions;
+  }
+  const { config } = ts.readConfigFile(configPath, ts.sys.readFile);
+  const { options } = ts.parseJsonConfigFileContent(config, ts.sys, path.dirname(configPath));
+  return { ...options, allowJs: true };
+}
+
+/** the outermost node spans exactly `start..end`, or the innermost node contains it */
+function findNode(sourceFile: ts.SourceFile, start: number, end: number): ts.Node | undefined {
+  let result: ts.Node | undefined;
+  const visit = (node: ts.Node) => {
+    if (node.getStart(sourceFile) > start || node.getEnd() < end) {
+      return;
+    }
+    if (!result || result.getStart(sourceFile) !== start || result.getEnd() !== end) {
+      result = node;
+    }
+    ts.forEachChild(node, visit);
+  };
+  ts.forEachChild(sourceFile, visit);
+  return result;
+}
+
+function everyMember(type: ts.Type, predicate: (type: ts.Type) => boolean): boolean {
+  return type.isUnion() ? type.types.every(predicate) : predicate(type);
+}
+
+function isIterable(checker: ts.TypeChecker, type: ts.Type): boolean {
+  return everyMember(
+    type,
+    member =>
+      !!(member.flags & ts.TypeFlags.StringLike) ||
+      checker
+        .getPropertiesOfType(checker.getApparentType(member))
+        .some(property => property.escapedName.toString().startsWith("__@iterator")),
+  );
+}
+
+function isArray(checker: ts.TypeChecker, type: ts.Type): boolean {
+  return everyMember(type, member => {
+    const apparent = checker.getApparentType(member);
+    const name = apparent.getSymbol()?.escapedName.toString();
+    if (name === "Array" || name === "ReadonlyArray") {
+      return true;
+    }
+    const objectFlags = apparent.flags & ts.TypeFlags.Object ? (apparent as ts.ObjectType).objectFlags : 0;
+    return (
+      !!(objectFlags & ts.ObjectFlags.Reference) &&
+      !!((apparent as ts.TypeReference).target.objectFlags & ts.ObjectFlags.Tuple)
+    );
+  });
+}
+
+function isThenable(checker: ts.TypeChecker, type: ts.Type): boolean {
+  return everyMember(type, member => {
+    const then = checker.getPropertyOfType(member, "then");
+    return !!then && !!then.valueDeclaration;
+  });
+}
+
+function isBoolean(type: ts.Type): boolean {
+  return everyMember(type, member => !!(member.flags & ts.TypeFlags.BooleanLike));
+}
+
+function updateFile(update: TypeQueryUpdate) {
+  const fileName = path.normalize(update.fileName);
+  compilerOptions = compilerOptions ?? loadCompilerOptions(fileName);
+  const previous = openFiles.get(fileName);
+  if (!previous || previous.text !== update.text) {
+    openFiles.set(fileName, { version: (previous?.version ?? 0) + 1, text: update.text });
+  }
+}
+
+function queryType(request: TypeQueryRequest): TypeInfo | null {
+  const fileName = path.normalize(request.fileName);
+  if (!openFiles.has(fileName)) {
+    return null;
+  }
+  const program = languageService.getProgram();
+  const sourceFile = program?.getSourceFile(fileName);
+  if (!program || !sourceFile) {
+    return null;
+  }
+  const node = findNode(sourceFile, request.start, request.end);
+  if (!node) {
+    return null;
+  }
+  const checker = program.getTypeChecker();
+  const type = checker.getTypeAtLocation(node);
+  return {
+    display: checker.typeToString(type, node),
+    iterable: isIterable(checker, type),
+    array: isArray(checker, type),
+    thenable: isThenable(checker, type),
+    boolean: isBoolean(type),
+  };
+}
+
+readline.createInterface({ input: process.stdin }).on("line", line => {
+  let message: TypeQueryMessage | undefined;
+  try {
+    message = JSON.parse(line);
+  } catch {}
+  if (!message) {
+    return;
+  }
+  if (message.method === "update") {
+    try {
+      updateFile(message);
+    } catch (e) {
+      console.error(e);
+    }
+    return;
+  }
+  let typeInfo: TypeInfo | null = null;
+  try {
+    typeInfo = queryType(message);
+  } catch (e) {
+    console.error(e);
+  }
+  process.stdout.write(JSON.stringify({ id: message.id, typeInfo }) + "\n");
+});